@@ -0,0 +1,2 @@
+1000000
+5000000000
@@ -0,0 +1,3 @@
+42
+21
+126
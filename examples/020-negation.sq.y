@@ -0,0 +1,4 @@
+-5
+5
+-300
+-5
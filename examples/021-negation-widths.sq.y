@@ -0,0 +1,8 @@
+-100
+-1000
+-100000
+-10000000000
+100
+1000
+100000
+10000000000
@@ -0,0 +1,7 @@
+-3
+21
+333
+1
+0
+0
+3
@@ -0,0 +1,3 @@
+1
+6
+-1
@@ -0,0 +1,8 @@
+1
+0
+1
+0
+0
+1
+99
+1
@@ -0,0 +1,4 @@
+0
+1
+1
+5
@@ -0,0 +1,7 @@
+8
+15
+9
+5
+4294967283
+240
+1
@@ -0,0 +1,5 @@
+1024
+8
+128
+-16
+1
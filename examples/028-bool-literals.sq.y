@@ -0,0 +1,5 @@
+1
+0
+1
+1
+2
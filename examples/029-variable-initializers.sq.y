@@ -0,0 +1,6 @@
+5
+300
+10
+5000000000
+1
+1000
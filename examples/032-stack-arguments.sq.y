@@ -0,0 +1,4 @@
+15
+45
+8
+36
@@ -0,0 +1,10 @@
+45
+3
+2
+1
+1
+3
+9
+27
+81
+45
@@ -0,0 +1,10 @@
+1
+3
+5
+7
+70
+71
+80
+81
+90
+91
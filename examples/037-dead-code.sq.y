@@ -0,0 +1,3 @@
+1
+4
+3
@@ -0,0 +1,6 @@
+A
+65
+122
+	
+q
+1
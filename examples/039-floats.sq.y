@@ -0,0 +1,5 @@
+1
+4
+1004
+3
+5
@@ -0,0 +1,9 @@
+200000000000
+0
+44
+-3
+253
+-56
+200000000000
+2
+B
@@ -0,0 +1,6 @@
+41
+42
+7
+100
+3
+1
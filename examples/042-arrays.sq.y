@@ -0,0 +1,6 @@
+42
+60
+-10
+3
+11
+22
@@ -0,0 +1,7 @@
+7
+100
+-300
+1
+1
+9
+7
@@ -0,0 +1,4 @@
+55
+3
+2
+1
@@ -0,0 +1,3 @@
+42
+Hi
+72
@@ -0,0 +1,7 @@
+255
+256
+127
+-128
+-128
+-32768
+44
@@ -0,0 +1,4 @@
+-100
+900
+-3
+-16
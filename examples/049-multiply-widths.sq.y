@@ -0,0 +1,5 @@
+4
+-63
+24464
+-120000
+20000000000
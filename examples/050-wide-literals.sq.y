@@ -0,0 +1,4 @@
+5000000000
+-5000000000
+18446744073709551615
+1
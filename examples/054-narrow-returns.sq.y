@@ -0,0 +1,5 @@
+42
+-5
+2000
+10
+-100
@@ -0,0 +1,7 @@
+10
+9
+12
+7
+-1
+7
+1
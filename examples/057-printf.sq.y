@@ -0,0 +1,5 @@
+x=42
+200 -300 5000000000
+1 2 3 4 5
+"quoted"	and\slashed
+ok
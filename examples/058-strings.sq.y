@@ -0,0 +1,4 @@
+hello
+hello
+h
+hello, world
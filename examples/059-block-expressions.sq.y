@@ -0,0 +1,6 @@
+4
+10
+30
+10
+11
+16
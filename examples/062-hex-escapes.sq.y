@@ -0,0 +1,6 @@
+A
+1
+z
+255
+Hi
+cut[a	b]
@@ -0,0 +1,3 @@
+41
+42
+84
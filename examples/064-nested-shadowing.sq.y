@@ -0,0 +1,8 @@
+100
+200
+10
+5
+5
+1
+2
+1001
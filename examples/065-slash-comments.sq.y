@@ -0,0 +1,2 @@
+42
+7
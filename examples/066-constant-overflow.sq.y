@@ -0,0 +1,3 @@
+300
+120005
+-200
@@ -0,0 +1,2 @@
+49
+1
@@ -0,0 +1,5 @@
+18446744073709551615
+4294967296
+2147483647
+-9223372036854775807
+18446744069414584319
@@ -0,0 +1,3 @@
+38
+123
+754
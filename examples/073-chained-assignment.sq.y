@@ -0,0 +1,9 @@
+5
+5
+200
+200
+200
+-21
+5
+1
+2
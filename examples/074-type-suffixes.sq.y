@@ -0,0 +1,7 @@
+7
+42
+42
+6
+9
+42
+21
@@ -0,0 +1,4 @@
+123456
+2340
+77
+123457
@@ -0,0 +1,9 @@
+1
+2
+4
+8
+1
+8
+20
+16
+2
@@ -0,0 +1,3 @@
+15
+180
+10
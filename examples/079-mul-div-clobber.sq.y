@@ -0,0 +1,4 @@
+35
+721
+-90
+39
@@ -0,0 +1,10 @@
+1
+1
+0
+0
+0
+1
+0
+1
+0
+1
@@ -0,0 +1 @@
+102
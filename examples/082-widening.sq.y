@@ -0,0 +1,7 @@
+200
+60000
+-5
+-300
+-70000
+4000000000
+-5
@@ -0,0 +1,2 @@
+ok
+3
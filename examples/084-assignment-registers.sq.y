@@ -0,0 +1,7 @@
+9
+0
+-18
+30
+5
+43
+13
@@ -5,6 +5,10 @@ void printbool(uint8_t x) {
     printf("%d\n", x);
 }
 
+void printchar(char x) {
+    printf("%c\n", x);
+}
+
 void print8(uint8_t x) {
     printf("%d\n", x);
 }
@@ -23,4 +27,19 @@ void print64(uint64_t x) {
 
 void printsum(uint32_t x, uint32_t y) {
     printf("%d\n", x + y);
-}
\ No newline at end of file
+}
+void printi8(int8_t x) {
+    printf("%d\n", x);
+}
+
+void printi16(int16_t x) {
+    printf("%d\n", x);
+}
+
+void printi32(int32_t x) {
+    printf("%d\n", x);
+}
+
+void printi64(int64_t x) {
+    printf("%ld\n", x);
+}
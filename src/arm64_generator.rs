@@ -0,0 +1,1231 @@
+use crate::ast::*;
+use crate::generator::*;
+use crate::scope::*;
+use crate::types::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Values smaller than 32 bits live in the w registers, their upper bits are
+// only cleaned up by the instructions which depend on them.
+const REGISTERS: &[&[&str]] = &[
+    &["w9", "w10", "w11", "w12"],
+    &["w9", "w10", "w11", "w12"],
+    &["w9", "w10", "w11", "w12"],
+    &["x9", "x10", "x11", "x12"],
+];
+
+const PARAM_REGISTERS: &[&[&str]] = &[
+    &["w0", "w1", "w2", "w3", "w4", "w5", "w6", "w7"],
+    &["w0", "w1", "w2", "w3", "w4", "w5", "w6", "w7"],
+    &["w0", "w1", "w2", "w3", "w4", "w5", "w6", "w7"],
+    &["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"],
+];
+
+const RESULT: &[&str] = &["w0", "w0", "w0", "x0"];
+
+/// The intra procedure call registers are free to use between two instructions
+const ADDRESS_REGISTER: &str = "x16";
+const TEMPORARY: &[&str] = &["w17", "w17", "w17", "x17"];
+
+const DATA_DIRECTIVES: &[&str] = &[".byte", ".short", ".long", ".quad"];
+
+const LOAD_INSTR: &[&str] = &["ldrb", "ldrh", "ldr", "ldr"];
+const STORE_INSTR: &[&str] = &["strb", "strh", "str", "str"];
+const ZERO_EXTEND_INSTR: &[&str] = &["uxtb", "uxth"];
+const SIGN_EXTEND_INSTR: &[&str] = &["sxtb", "sxth"];
+
+/// The shift which turns an array index into a byte offset
+const INDEX_SHIFT: &[usize] = &[0, 1, 2, 3];
+
+/// Loads and stores with a negative offset from the frame pointer can only
+/// reach this far in a single instruction.
+const MAX_FRAME_OFFSET: i32 = 256;
+
+fn comparison_to_condition(comparison_type: BinaryOperationType, signed: bool) -> &'static str {
+    match comparison_type {
+        BinaryOperationType::Equals => "eq",
+        BinaryOperationType::NotEquals => "ne",
+        BinaryOperationType::LessThan if signed => "lt",
+        BinaryOperationType::LessThan => "lo",
+        BinaryOperationType::LessThanOrEqual if signed => "le",
+        BinaryOperationType::LessThanOrEqual => "ls",
+        BinaryOperationType::GreaterThan if signed => "gt",
+        BinaryOperationType::GreaterThan => "hi",
+        BinaryOperationType::GreaterThanOrEqual if signed => "ge",
+        BinaryOperationType::GreaterThanOrEqual => "hs",
+        _ => panic!(
+            "Trying to get a condition for a non comparison operator, {:?}",
+            comparison_type
+        ),
+    }
+}
+
+/// The builtin print functions declared by the parser. Each one moves its
+/// arguments into the value operand of printf and prints it using a format string.
+const RUNTIME_FUNCTIONS: &[(&str, &str, &str)] = &[
+    ("printbool", "and\tw1, w0, #0xff", ".Lformat_d"),
+    ("printchar", "and\tw1, w0, #0xff", ".Lformat_c"),
+    ("print8", "and\tw1, w0, #0xff", ".Lformat_d"),
+    ("print16", "and\tw1, w0, #0xffff", ".Lformat_d"),
+    ("print32", "mov\tw1, w0", ".Lformat_u"),
+    ("print64", "mov\tx1, x0", ".Lformat_lu"),
+    ("printi8", "sxtb\tw1, w0", ".Lformat_d"),
+    ("printi16", "sxth\tw1, w0", ".Lformat_d"),
+    ("printi32", "mov\tw1, w0", ".Lformat_d"),
+    ("printi64", "mov\tx1, x0", ".Lformat_ld"),
+    ("printsum", "add\tw1, w0, w1", ".Lformat_d"),
+];
+
+const RUNTIME_FORMATS: &[(&str, &str)] = &[
+    (".Lformat_c", "%c\\n"),
+    (".Lformat_d", "%d\\n"),
+    (".Lformat_u", "%u\\n"),
+    (".Lformat_lu", "%lu\\n"),
+    (".Lformat_ld", "%ld\\n"),
+];
+
+/// Generates assembly for 64 bit ARM processors running Linux
+///
+/// Floating point values aren't supported by this backend yet.
+pub struct Arm64CodeGenerator {
+    output_path: String,
+    /// The generated assembly, it is only written out when generation is finished
+    output: String,
+    /// Whether the generated assembly is also echoed to stdout for debugging
+    echo: bool,
+    registers: RegisterFile,
+    /// The number of labels which were created for every prefix
+    label_counts: HashMap<&'static str, i32>,
+    return_label: String,
+    parameter_area: i32,
+    /// The continue and break labels of the loops surrounding the current node
+    loop_labels: Vec<(String, String)>,
+    /// The string literals which are emitted into .rodata, labeled by their index
+    string_literals: Vec<String>,
+}
+
+impl Arm64CodeGenerator {
+    /// Returns the assembly generated so far
+    pub fn emitted(&self) -> &str {
+        &self.output
+    }
+
+    fn unsupported_float(&self) {
+        self.error("Floating point values are not supported by the AArch64 backend yet");
+    }
+
+    /// Moves the stack pointer, the amount has to keep it 16 byte aligned
+    fn adjust_stack(&mut self, instr: &str, amount: i32) {
+        if amount == 0 {
+            return;
+        }
+
+        // Immediates only have 12 bits
+        if amount < 4096 {
+            self.write(&format!("\t{}\tsp, sp, #{}", instr, amount));
+        } else {
+            self.gen_load_immediate(ADDRESS_REGISTER, amount as u64);
+            self.write(&format!("\t{}\tsp, sp, {}", instr, ADDRESS_REGISTER));
+        }
+    }
+
+    /// Loads a 64 bit value 16 bits at a time
+    fn gen_load_immediate(&mut self, register: &str, value: u64) {
+        self.write(&format!("\tmov\t{}, #{}", register, value & 0xffff));
+
+        for shift in [16, 32, 48] {
+            let chunk = (value >> shift) & 0xffff;
+            if chunk != 0 {
+                self.write(&format!("\tmovk\t{}, #{}, lsl #{}", register, chunk, shift));
+            }
+        }
+    }
+
+    /// Computes the address of a symbol into the address register
+    fn gen_symbol_address(&mut self, symbol: &Symbol) {
+        match symbol.symbol_type {
+            SymbolType::Global => {
+                self.write(&format!("\tadrp\t{}, {}", ADDRESS_REGISTER, symbol.name));
+                self.write(&format!(
+                    "\tadd\t{}, {}, :lo12:{}",
+                    ADDRESS_REGISTER, ADDRESS_REGISTER, symbol.name
+                ));
+            }
+            _ => {
+                let offset = self.get_frame_offset(symbol);
+                let instr = if offset < 0 { "sub" } else { "add" };
+
+                // Like with the stack pointer, larger offsets don't fit in the immediate
+                if offset.abs() < 4096 {
+                    self.write(&format!(
+                        "\t{}\t{}, x29, #{}",
+                        instr,
+                        ADDRESS_REGISTER,
+                        offset.abs()
+                    ));
+                } else {
+                    self.gen_load_immediate(ADDRESS_REGISTER, offset.unsigned_abs() as u64);
+                    self.write(&format!(
+                        "\t{}\t{}, x29, {}",
+                        instr, ADDRESS_REGISTER, ADDRESS_REGISTER
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Returns the offset of a variable or parameter from the frame pointer
+    ///
+    /// Register parameters are spilled right below the frame pointer, followed by
+    /// the local variables. Parameters beyond the eighth are passed on the stack
+    /// by the caller and live above the saved frame pointer and link register.
+    fn get_frame_offset(&self, symbol: &Symbol) -> i32 {
+        match symbol.symbol_type {
+            SymbolType::Variable => -(self.parameter_area + symbol.offset),
+            SymbolType::FunctionParameter => {
+                let index = symbol.offset as usize;
+
+                if index < PARAM_REGISTERS[0].len() {
+                    -((index as i32 + 1) * 8)
+                } else {
+                    16 + (index - PARAM_REGISTERS[0].len()) as i32 * 8
+                }
+            }
+            _ => {
+                self.error("Trying to get the frame offset of a symbol outside of the frame");
+                unreachable!();
+            }
+        }
+    }
+
+    /// Returns the memory operand of a variable, parameter or global
+    fn get_symbol_address(&mut self, symbol: &Symbol) -> String {
+        if symbol.symbol_type == SymbolType::Function {
+            self.error("Trying to get the address of a function symbol");
+        }
+
+        if symbol.symbol_type != SymbolType::Global {
+            let offset = self.get_frame_offset(symbol);
+
+            if offset >= -MAX_FRAME_OFFSET {
+                return format!("[x29, #{}]", offset);
+            }
+        }
+
+        self.gen_symbol_address(symbol);
+        format!("[{}]", ADDRESS_REGISTER)
+    }
+
+    /// Returns the memory operand of an array element, widening the index so it
+    /// can be used as the offset register of the address.
+    fn get_element_address(
+        &mut self,
+        symbol: &Symbol,
+        index_reg: RegisterGuard,
+    ) -> (String, RegisterGuard) {
+        let element_size = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type.get_size(),
+            _ => {
+                self.error("Trying to index a symbol which is not an array");
+                unreachable!();
+            }
+        };
+
+        let index_reg = if index_reg.size < 64 {
+            let src_index = Self::size_to_instruction_index(index_reg.size);
+            self.gen_widen_instr(index_reg, &PrimitiveType::UInt64, src_index, 3, false)
+        } else {
+            index_reg
+        };
+
+        self.gen_symbol_address(symbol);
+
+        let shift = INDEX_SHIFT[Self::size_to_instruction_index(element_size)];
+        let address = if shift == 0 {
+            format!("[{}, {}]", ADDRESS_REGISTER, REGISTERS[3][index_reg.index])
+        } else {
+            format!(
+                "[{}, {}, lsl #{}]",
+                ADDRESS_REGISTER, REGISTERS[3][index_reg.index], shift
+            )
+        };
+
+        (address, index_reg)
+    }
+
+    /// Clears the upper bits of a value smaller than 32 bits, for the
+    /// instructions which read the whole register.
+    fn gen_extend(&mut self, register: &str, size_index: usize, signed: bool) {
+        if size_index >= 2 {
+            return;
+        }
+
+        let instr = if signed {
+            SIGN_EXTEND_INSTR[size_index]
+        } else {
+            ZERO_EXTEND_INSTR[size_index]
+        };
+        self.write(&format!("\t{}\t{}, {}", instr, register, register));
+    }
+
+    fn gen_register_move(&mut self, source: &Register, destination: &Register, size_index: usize) {
+        self.write(&format!(
+            "\tmov\t{}, {}",
+            REGISTERS[size_index][destination.index], REGISTERS[size_index][source.index]
+        ));
+    }
+
+    /// Applies an instruction taking two source registers, the result replaces
+    /// the left operand.
+    fn gen_binary_instr(
+        &mut self,
+        instr: &str,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.write(&format!(
+            "\t{}\t{}, {}, {}",
+            instr,
+            REGISTERS[size_index][left_reg.index],
+            REGISTERS[size_index][left_reg.index],
+            REGISTERS[size_index][right_reg.index]
+        ));
+
+        drop(right_reg);
+        left_reg
+    }
+
+    /// Divides the left operand by the right one, leaving the quotient in the
+    /// given register.
+    fn gen_division(
+        &mut self,
+        left_reg: Register,
+        right_reg: Register,
+        quotient: &str,
+        size_index: usize,
+        signed: bool,
+    ) {
+        let left = REGISTERS[size_index][left_reg.index];
+        let right = REGISTERS[size_index][right_reg.index];
+
+        self.gen_extend(left, size_index, signed);
+        self.gen_extend(right, size_index, signed);
+
+        self.write(&format!(
+            "\t{}\t{}, {}, {}",
+            if signed { "sdiv" } else { "udiv" },
+            quotient,
+            left,
+            right
+        ));
+    }
+
+    /// Calls a function, the arguments are passed in registers and on the stack
+    fn gen_call(
+        &mut self,
+        name: &str,
+        params: &[AstNode],
+        return_type: &PrimitiveType,
+    ) -> Option<RegisterGuard> {
+        // The scratch registers are caller saved so they have to be preserved
+        // across the call when they still hold a live value.
+        let live_regs: Vec<Register> = self.registers.borrow().iter().flatten().copied().collect();
+
+        for reg in &live_regs {
+            self.write(&format!("\tstr\t{}, [sp, #-16]!", REGISTERS[3][reg.index]));
+        }
+
+        let register_param_count = params.len().min(PARAM_REGISTERS[0].len());
+        let stack_param_count = params.len() - register_param_count;
+
+        // The stack passed arguments start right after the register ones, so
+        // they are at the stack pointer once the register ones are loaded.
+        let stack_area = (stack_param_count as i32 * 8 + 15) / 16 * 16;
+        let register_area = if stack_param_count > 0 {
+            PARAM_REGISTERS[0].len() as i32 * 8
+        } else {
+            (register_param_count as i32 * 8 + 15) / 16 * 16
+        };
+
+        self.adjust_stack("sub", register_area + stack_area);
+
+        // All arguments are evaluated into the argument area first, so calls in
+        // the arguments don't overwrite the parameter registers.
+        for (index, param) in params.iter().enumerate() {
+            let primitive_type = param.get_primitive_type();
+            let mut expression_reg = self.gen_expression(param);
+
+            if primitive_type.get_size() < 64 {
+                expression_reg = self.gen_widen_instr(
+                    expression_reg,
+                    &PrimitiveType::UInt64,
+                    Self::size_to_instruction_index(primitive_type.get_size()),
+                    3,
+                    primitive_type.is_signed(),
+                );
+            }
+
+            self.write(&format!(
+                "\tstr\t{}, [sp, #{}]",
+                REGISTERS[3][expression_reg.index],
+                index * 8
+            ));
+
+            drop(expression_reg);
+        }
+
+        for (index, param_reg) in PARAM_REGISTERS[3]
+            .iter()
+            .take(register_param_count)
+            .enumerate()
+        {
+            self.write(&format!("\tldr\t{}, [sp, #{}]", param_reg, index * 8));
+        }
+        self.adjust_stack("add", register_area);
+
+        self.write(&format!("\tbl\t{}", name));
+
+        self.adjust_stack("add", stack_area);
+
+        for reg in live_regs.iter().rev() {
+            self.write(&format!("\tldr\t{}, [sp], #16", REGISTERS[3][reg.index]));
+        }
+
+        if *return_type == PrimitiveType::Void {
+            return None;
+        }
+
+        let size = return_type.get_size();
+        let register = self.get_register(size);
+        let index = Self::size_to_instruction_index(size);
+
+        self.write(&format!(
+            "\tmov\t{}, {}",
+            REGISTERS[index][register.index], RESULT[index]
+        ));
+
+        Some(register)
+    }
+
+    fn gen_condition_jump(&mut self, condition: &AstNode, label: &str) {
+        let condition_reg = self.gen_expression(condition);
+        let instr_index = Self::size_to_instruction_index(condition_reg.size);
+
+        self.write(&format!(
+            "\tcbz\t{}, {}",
+            REGISTERS[instr_index][condition_reg.index], label
+        ));
+    }
+}
+
+impl CodeGenerator for Arm64CodeGenerator {
+    fn new(output_path: &str, verbose: bool, _optimization_level: u32) -> Self {
+        Arm64CodeGenerator {
+            output_path: output_path.to_string(),
+            output: String::new(),
+            echo: verbose && output_path != "-",
+            registers: Rc::new(RefCell::new(vec![None; REGISTERS[0].len()])),
+            label_counts: HashMap::new(),
+            return_label: String::new(),
+            parameter_area: 0,
+            loop_labels: Vec::new(),
+            string_literals: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, data: &str) {
+        self.output.push_str(data);
+        self.output.push('\n');
+    }
+
+    fn finish(&mut self) {
+        if self.echo {
+            print!("{}", self.emitted());
+        }
+
+        // An output path of - writes the assembly to stdout
+        let mut file: Box<dyn Write> = if self.output_path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(&self.output_path).expect("Failed to create output file"))
+        };
+
+        file.write_all(self.output.as_bytes())
+            .expect("Failed to write to output file");
+    }
+
+    fn get_named_label(&mut self, prefix: &'static str) -> String {
+        let count = self.label_counts.entry(prefix).or_insert(0);
+        let label = format!(".L{}_{}", prefix, count);
+        *count += 1;
+        label
+    }
+
+    fn get_register(&mut self, size: i32) -> RegisterGuard {
+        let free_index = self.registers.borrow().iter().position(|r| r.is_none());
+        if let Some(index) = free_index {
+            let register = Register {
+                size,
+                index,
+                float: false,
+            };
+            return RegisterGuard::new(register, Rc::clone(&self.registers));
+        }
+
+        self.error("Out of registers!");
+        unreachable!();
+    }
+
+    fn get_float_register(&mut self, _size: i32) -> RegisterGuard {
+        self.unsupported_float();
+        unreachable!();
+    }
+
+    fn gen_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        register: RegisterGuard,
+        size_index: usize,
+    ) {
+        let address = self.get_symbol_address(symbol);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            STORE_INSTR[size_index], REGISTERS[size_index][register.index], address
+        ));
+    }
+
+    fn gen_comparison_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        comparison_type: BinaryOperationType,
+        signed: bool,
+    ) -> RegisterGuard {
+        let left = REGISTERS[size_index][left_reg.index];
+        let right = REGISTERS[size_index][right_reg.index];
+
+        self.gen_extend(left, size_index, signed);
+        self.gen_extend(right, size_index, signed);
+
+        self.write(&format!("\tcmp\t{}, {}", left, right));
+        self.write(&format!(
+            "\tcset\t{}, {}",
+            REGISTERS[0][right_reg.index],
+            comparison_to_condition(comparison_type, signed)
+        ));
+
+        drop(left_reg);
+        right_reg
+    }
+
+    fn gen_add_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary_instr("add", left_reg, right_reg, size_index)
+    }
+
+    fn gen_float_add_instr(
+        &mut self,
+        _left_reg: RegisterGuard,
+        _right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.unsupported_float();
+        unreachable!();
+    }
+
+    fn gen_subtract_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary_instr("sub", left_reg, right_reg, size_index)
+    }
+
+    fn gen_multiply_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        _signed: bool,
+    ) -> RegisterGuard {
+        // The low half of the product is the same for signed and unsigned operands
+        self.gen_binary_instr("mul", left_reg, right_reg, size_index)
+    }
+
+    fn gen_divide_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        let quotient = REGISTERS[size_index][left_reg.index];
+        self.gen_division(*left_reg, *right_reg, quotient, size_index, signed);
+
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_modulo_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        let quotient = TEMPORARY[size_index];
+        self.gen_division(*left_reg, *right_reg, quotient, size_index, signed);
+
+        // The remainder is what is left after subtracting quotient * divisor
+        let left = REGISTERS[size_index][left_reg.index];
+        self.write(&format!(
+            "\tmsub\t{}, {}, {}, {}",
+            left, quotient, REGISTERS[size_index][right_reg.index], left
+        ));
+
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_bitand_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary_instr("and", left_reg, right_reg, size_index)
+    }
+
+    fn gen_bitor_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary_instr("orr", left_reg, right_reg, size_index)
+    }
+
+    fn gen_bitxor_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary_instr("eor", left_reg, right_reg, size_index)
+    }
+
+    fn gen_shift_left_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_extend(REGISTERS[size_index][right_reg.index], size_index, false);
+
+        self.gen_binary_instr("lsl", left_reg, right_reg, size_index)
+    }
+
+    fn gen_shift_right_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        // The bits shifted in from above have to match the narrow value
+        self.gen_extend(REGISTERS[size_index][left_reg.index], size_index, signed);
+        self.gen_extend(REGISTERS[size_index][right_reg.index], size_index, false);
+
+        let instr = if signed { "asr" } else { "lsr" };
+        self.gen_binary_instr(instr, left_reg, right_reg, size_index)
+    }
+
+    fn gen_logical_instr(
+        &mut self,
+        left: &AstNode,
+        right: &AstNode,
+        operation_type: BinaryOperationType,
+    ) -> RegisterGuard {
+        let end_label = self.get_named_label("logical_end");
+
+        let left_reg = self.gen_expression(left);
+
+        // Skip the right operand when the left one already determines the result
+        let instr = match operation_type {
+            BinaryOperationType::LogicalAnd => "cbz",
+            BinaryOperationType::LogicalOr => "cbnz",
+            _ => {
+                self.error("Trying to generate a logical instruction for a non logical operator");
+                unreachable!();
+            }
+        };
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            instr, REGISTERS[0][left_reg.index], end_label
+        ));
+
+        let right_reg = self.gen_expression(right);
+        self.gen_register_move(&right_reg, &left_reg, 0);
+        drop(right_reg);
+
+        self.write(&format!("{}:", end_label));
+
+        left_reg
+    }
+
+    fn gen_ternary_instr(
+        &mut self,
+        condition: &AstNode,
+        then_value: &AstNode,
+        else_value: &AstNode,
+    ) -> RegisterGuard {
+        let else_label = self.get_named_label("ternary_else");
+        let end_label = self.get_named_label("ternary_end");
+
+        self.gen_condition_jump(condition, &else_label);
+
+        // Both branches move their value into the same result register
+        let primitive_type = then_value.get_primitive_type();
+        let size_index = Self::size_to_instruction_index(primitive_type.get_size());
+        let result_reg = self.get_register(primitive_type.get_size());
+
+        let then_reg = self.gen_expression(then_value);
+        self.gen_register_move(&then_reg, &result_reg, size_index);
+        drop(then_reg);
+        self.write(&format!("\tb\t{}", end_label));
+
+        self.write(&format!("{}:", else_label));
+        let else_reg = self.gen_expression(else_value);
+        self.gen_register_move(&else_reg, &result_reg, size_index);
+        drop(else_reg);
+
+        self.write(&format!("{}:", end_label));
+
+        result_reg
+    }
+
+    fn gen_negate_instr(&mut self, register: RegisterGuard, size_index: usize) -> RegisterGuard {
+        let name = REGISTERS[size_index][register.index];
+        self.write(&format!("\tneg\t{}, {}", name, name));
+
+        register
+    }
+
+    fn gen_not_instr(&mut self, register: RegisterGuard) -> RegisterGuard {
+        let name = REGISTERS[0][register.index];
+        self.write(&format!("\teor\t{}, {}, #1", name, name));
+
+        register
+    }
+
+    fn gen_bitnot_instr(&mut self, register: RegisterGuard, size_index: usize) -> RegisterGuard {
+        let name = REGISTERS[size_index][register.index];
+        self.write(&format!("\tmvn\t{}, {}", name, name));
+
+        register
+    }
+
+    fn gen_numeric_literal_instr(
+        &mut self,
+        primitive_type: &PrimitiveType,
+        primitive_value: &PrimitiveValue,
+    ) -> RegisterGuard {
+        if primitive_type.is_float() {
+            self.unsupported_float();
+        }
+
+        let register = self.get_register(primitive_type.get_size());
+        self.gen_load_immediate(
+            REGISTERS[3][register.index],
+            primitive_value.as_u64(primitive_type),
+        );
+
+        register
+    }
+
+    fn gen_widen_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+        src_index: usize,
+        dest_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        if primitive_type.is_float() {
+            self.unsupported_float();
+        }
+
+        let result_reg = self.get_register(primitive_type.get_size());
+        let source = REGISTERS[src_index][register.index];
+
+        if src_index < 2 {
+            // Extending into a w register also clears the upper half of the x register
+            let (instr, dest_index) = if signed {
+                (SIGN_EXTEND_INSTR[src_index], dest_index)
+            } else {
+                (ZERO_EXTEND_INSTR[src_index], 2)
+            };
+
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                instr, REGISTERS[dest_index][result_reg.index], source
+            ));
+        } else if signed {
+            self.write(&format!(
+                "\tsxtw\t{}, {}",
+                REGISTERS[dest_index][result_reg.index], source
+            ));
+        } else {
+            // Writing a 32 bit register implicitly zeroes the upper half
+            self.write(&format!(
+                "\tmov\t{}, {}",
+                REGISTERS[src_index][result_reg.index], source
+            ));
+        }
+
+        drop(register);
+
+        result_reg
+    }
+
+    fn gen_cast_instr(
+        &mut self,
+        register: RegisterGuard,
+        src_type: &PrimitiveType,
+        dest_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        if src_type.is_float() || dest_type.is_float() {
+            self.unsupported_float();
+        }
+
+        let src_size = src_type.get_size();
+        let dest_size = dest_type.get_size();
+
+        if dest_size > src_size {
+            return self.gen_widen_instr(
+                register,
+                dest_type,
+                Self::size_to_instruction_index(src_size),
+                Self::size_to_instruction_index(dest_size),
+                src_type.is_signed(),
+            );
+        }
+
+        // A narrower value is just the low bits of the same register
+        register.with_size(dest_size)
+    }
+
+    fn gen_identifier_instr(&mut self, symbol: &Symbol) -> RegisterGuard {
+        if symbol.primitive_type.is_float() {
+            self.unsupported_float();
+        }
+
+        let size = symbol.primitive_type.get_size();
+        let index = Self::size_to_instruction_index(size);
+        let address = self.get_symbol_address(symbol);
+        let register = self.get_register(size);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            LOAD_INSTR[index], REGISTERS[index][register.index], address
+        ));
+
+        register
+    }
+
+    fn gen_address_of_instr(&mut self, symbol: &Symbol) -> RegisterGuard {
+        let register = self.get_register(64);
+
+        self.gen_symbol_address(symbol);
+        self.write(&format!(
+            "\tmov\t{}, {}",
+            REGISTERS[3][register.index], ADDRESS_REGISTER
+        ));
+
+        register
+    }
+
+    fn gen_dereference_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        if primitive_type.is_float() {
+            self.unsupported_float();
+        }
+
+        let size = primitive_type.get_size();
+        let index = Self::size_to_instruction_index(size);
+
+        // The loaded value can replace the pointer in the same register
+        self.write(&format!(
+            "\t{}\t{}, [{}]",
+            LOAD_INSTR[index], REGISTERS[index][register.index], REGISTERS[3][register.index]
+        ));
+
+        register.with_size(size)
+    }
+
+    fn gen_index_instr(&mut self, symbol: &Symbol, index_reg: RegisterGuard) -> RegisterGuard {
+        let element_type = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type.as_ref().clone(),
+            _ => {
+                self.error("Trying to index a symbol which is not an array");
+                unreachable!();
+            }
+        };
+        if element_type.is_float() {
+            self.unsupported_float();
+        }
+
+        let (address, index_reg) = self.get_element_address(symbol, index_reg);
+        let size = element_type.get_size();
+        let index = Self::size_to_instruction_index(size);
+        let register = self.get_register(size);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            LOAD_INSTR[index], REGISTERS[index][register.index], address
+        ));
+        drop(index_reg);
+
+        register
+    }
+
+    fn gen_index_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        index_reg: RegisterGuard,
+        register: RegisterGuard,
+    ) {
+        let (address, index_reg) = self.get_element_address(symbol, index_reg);
+        let index = Self::size_to_instruction_index(register.size);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            STORE_INSTR[index], REGISTERS[index][register.index], address
+        ));
+
+        drop(index_reg);
+        drop(register);
+    }
+
+    fn gen_store_instr(
+        &mut self,
+        pointer_reg: RegisterGuard,
+        register: RegisterGuard,
+        size_index: usize,
+    ) {
+        self.write(&format!(
+            "\t{}\t{}, [{}]",
+            STORE_INSTR[size_index],
+            REGISTERS[size_index][register.index],
+            REGISTERS[3][pointer_reg.index]
+        ));
+
+        drop(pointer_reg);
+        drop(register);
+    }
+
+    fn gen_functioncall_instr(
+        &mut self,
+        symbol: &Symbol,
+        params: &[AstNode],
+    ) -> Option<RegisterGuard> {
+        self.gen_call(&symbol.name, params, &symbol.primitive_type)
+    }
+
+    fn gen_printf_instr(&mut self, params: &[AstNode]) {
+        // Variadic arguments are passed like any other argument on Linux
+        self.gen_call("printf", params, &PrimitiveType::Void);
+    }
+
+    fn gen_assert_instr(&mut self, condition: &AstNode) {
+        let condition_reg = self.gen_expression(condition);
+        let end_label = self.get_named_label("assert_end");
+
+        let instr_index = Self::size_to_instruction_index(condition_reg.size);
+
+        self.write(&format!(
+            "\tcbnz\t{}, {}",
+            REGISTERS[instr_index][condition_reg.index], end_label
+        ));
+        self.write("\tbrk\t#1");
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_string_literal_instr(&mut self, value: &str) -> RegisterGuard {
+        // Identical strings share the same label
+        let index = match self.string_literals.iter().position(|s| s == value) {
+            Some(index) => index,
+            None => {
+                self.string_literals.push(value.to_string());
+                self.string_literals.len() - 1
+            }
+        };
+
+        let register = self.get_register(64);
+        let name = REGISTERS[3][register.index];
+        self.write(&format!("\tadrp\t{}, .LC{}", name, index));
+        self.write(&format!("\tadd\t{}, {}, :lo12:.LC{}", name, name, index));
+
+        register
+    }
+
+    fn gen_string_literals(&mut self) {
+        if self.string_literals.is_empty() {
+            return;
+        }
+
+        self.write("\t.section\t.rodata");
+        for (index, value) in std::mem::take(&mut self.string_literals).iter().enumerate() {
+            self.write(&format!(".LC{}:", index));
+            self.write(&format!("\t.asciz\t\"{}\"", escape_string(value)));
+        }
+        self.write("\t.text");
+    }
+
+    fn gen_if_instr(
+        &mut self,
+        condition: &AstNode,
+        code: &AstNode,
+        else_code: &Option<Box<AstNode>>,
+    ) {
+        let else_label = self.get_named_label("if_else");
+        let end_label = self.get_named_label("if_end");
+
+        let has_else = else_code.is_some();
+        self.gen_condition_jump(condition, if has_else { &else_label } else { &end_label });
+
+        self.gen_node(code);
+        if let Some(else_code) = else_code {
+            self.write(&format!("\tb\t{}", end_label));
+            self.write(&format!("{}:", else_label));
+            self.gen_node(else_code);
+        }
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_while_instr(&mut self, condition: &AstNode, code: &AstNode) {
+        let start_label = self.get_named_label("while_start");
+        let end_label = self.get_named_label("while_end");
+
+        self.write(&format!("{}:", start_label));
+        self.gen_condition_jump(condition, &end_label);
+
+        self.loop_labels
+            .push((start_label.clone(), end_label.clone()));
+        self.gen_node(code);
+        self.loop_labels.pop();
+
+        self.write(&format!("\tb\t{}", start_label));
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_for_instr(
+        &mut self,
+        init: &AstNode,
+        condition: &AstNode,
+        update: &AstNode,
+        code: &AstNode,
+    ) {
+        self.gen_node(init);
+
+        let start_label = self.get_named_label("for_start");
+        let update_label = self.get_named_label("for_update");
+        let end_label = self.get_named_label("for_end");
+
+        self.write(&format!("{}:", start_label));
+        self.gen_condition_jump(condition, &end_label);
+
+        // A continue still has to run the update before the next iteration
+        self.loop_labels
+            .push((update_label.clone(), end_label.clone()));
+        self.gen_node(code);
+        self.loop_labels.pop();
+
+        self.write(&format!("{}:", update_label));
+        self.gen_node(update);
+
+        self.write(&format!("\tb\t{}", start_label));
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_function_instr(&mut self, symbol: &Symbol, code: &AstNode) {
+        assert!(symbol.symbol_type == SymbolType::Function);
+
+        self.return_label = self.get_named_label("return");
+
+        let register_param_count = symbol.parameter_types.len().min(PARAM_REGISTERS[0].len());
+        self.parameter_area = register_param_count as i32 * 8;
+
+        // The stack pointer always has to stay 16 byte aligned
+        let frame_size = (self.parameter_area + code.get_stack_size() + 15) / 16 * 16;
+
+        self.write("\t.p2align\t2");
+        self.write(&format!("\t.type\t{}, %function", symbol.name));
+        self.write(&format!("{}:", symbol.name));
+        self.write("\tstp\tx29, x30, [sp, #-16]!");
+        self.write("\tmov\tx29, sp");
+        self.adjust_stack("sub", frame_size);
+
+        // Spill the register parameters so they survive calls made by this function
+        for (index, param_reg) in PARAM_REGISTERS[3]
+            .iter()
+            .take(register_param_count)
+            .enumerate()
+        {
+            self.write(&format!(
+                "\tstr\t{}, [x29, #-{}]",
+                param_reg,
+                (index + 1) * 8
+            ));
+        }
+
+        self.gen_node(code);
+        self.write(&format!("{}:", self.return_label));
+
+        // The process exit status is taken from main, so make it well defined
+        if symbol.name == "main" && symbol.primitive_type == PrimitiveType::Void {
+            self.write("\tmov\tw0, #0");
+        }
+
+        self.write("\tmov\tsp, x29");
+        self.write("\tldp\tx29, x30, [sp], #16");
+        self.write("\tret");
+        self.write(&format!("\t.size\t{}, .-{}", symbol.name, symbol.name));
+    }
+
+    fn gen_global_declaration_instr(
+        &mut self,
+        symbol: &Symbol,
+        initializer: &Option<Box<AstNode>>,
+    ) {
+        let size = symbol.primitive_type.get_size() / 8;
+        let alignment = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type.get_size() / 8,
+            _ => size,
+        };
+
+        match initializer {
+            Some(initializer) => {
+                let value = match initializer.get_constant_bits(&symbol.primitive_type) {
+                    Some(value) => value,
+                    None => {
+                        self.error("Global variables need a constant initializer");
+                        unreachable!();
+                    }
+                };
+
+                self.write("\t.data");
+                self.write(&format!("\t.balign\t{}", alignment));
+                self.write(&format!("{}:", symbol.name));
+                self.write(&format!(
+                    "\t{}\t{}",
+                    DATA_DIRECTIVES[Self::size_to_instruction_index(size * 8)],
+                    value as i64
+                ));
+            }
+            None => {
+                self.write("\t.bss");
+                self.write(&format!("\t.balign\t{}", alignment));
+                self.write(&format!("{}:", symbol.name));
+                self.write(&format!("\t.zero\t{}", size));
+            }
+        }
+
+        self.write("\t.text");
+    }
+
+    fn gen_return_instr(&mut self, value: &Option<Box<AstNode>>) {
+        if let Some(value) = value {
+            let register = self.gen_expression(value);
+            let instr_index = Self::size_to_instruction_index(register.size);
+
+            self.write(&format!(
+                "\tmov\t{}, {}",
+                RESULT[instr_index], REGISTERS[instr_index][register.index]
+            ));
+
+            // Narrow values are extended to 32 bits, so callers which read all of
+            // w0, like the exit status of main, see the right value
+            self.gen_extend(
+                RESULT[instr_index],
+                instr_index,
+                value.get_primitive_type().is_signed(),
+            );
+
+            drop(register);
+        }
+
+        self.write(&format!("\tb\t{}", self.return_label));
+    }
+
+    fn gen_break_instr(&mut self, span: Span) {
+        let end_label = match self.loop_labels.last() {
+            Some((_, end_label)) => end_label.clone(),
+            None => {
+                self.error_at(span, "Break statement outside of a loop");
+                unreachable!();
+            }
+        };
+
+        self.write(&format!("\tb\t{}", end_label));
+    }
+
+    fn gen_continue_instr(&mut self, span: Span) {
+        let continue_label = match self.loop_labels.last() {
+            Some((continue_label, _)) => continue_label.clone(),
+            None => {
+                self.error_at(span, "Continue statement outside of a loop");
+                unreachable!();
+            }
+        };
+
+        self.write(&format!("\tb\t{}", continue_label));
+    }
+
+    fn gen_runtime(&mut self) {
+        for (name, argument_instr, format) in RUNTIME_FUNCTIONS {
+            self.write("\t.p2align\t2");
+            self.write(&format!("{}:", name));
+            self.write("\tstp\tx29, x30, [sp, #-16]!");
+            self.write("\tmov\tx29, sp");
+            self.write(&format!("\t{}", argument_instr));
+            self.write(&format!("\tadrp\tx0, {}", format));
+            self.write(&format!("\tadd\tx0, x0, :lo12:{}", format));
+            self.write("\tbl\tprintf");
+            self.write("\tldp\tx29, x30, [sp], #16");
+            self.write("\tret");
+        }
+
+        self.write("\t.section\t.rodata");
+        for (label, format) in RUNTIME_FORMATS {
+            self.write(&format!("{}:", label));
+            self.write(&format!("\t.string\t\"{}\"", format));
+        }
+    }
+
+    fn do_post_check(&self) -> bool {
+        let registers = self.registers.borrow();
+        for register in registers.iter() {
+            if register.is_some() {
+                self.error("Not all registers were freed!");
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -1,5 +1,5 @@
-use crate::types::*;
 use crate::scope::*;
+use crate::types::*;
 
 #[derive(Debug, Clone, Copy)]
 pub enum BinaryOperationType {
@@ -7,79 +7,221 @@ pub enum BinaryOperationType {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
     Equals,
     NotEquals,
     LessThan,
     LessThanOrEqual,
     GreaterThan,
     GreaterThanOrEqual,
+    LogicalAnd,
+    LogicalOr,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 }
 
-//#[derive(Debug, Clone, Copy)]
-//pub enum UnaryOperationType {
-//    Negate,
-//}
+#[derive(Debug, Clone, Copy)]
+pub enum UnaryOperationType {
+    Negate,
+    Not,
+    BitNot,
+}
+
+/// The position in the source a node was parsed from
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
 
 pub enum AstNode {
-    BinaryOperation(BinaryOperationType, Box<AstNode>, Box<AstNode>),
-    //  UnaryOperation(UnaryOperationType, Box<AstNode>),
+    BinaryOperation(BinaryOperationType, Box<AstNode>, Box<AstNode>, Span),
+    UnaryOperation(UnaryOperationType, Box<AstNode>),
     NumericLiteral(PrimitiveType, PrimitiveValue),
-    VariableDeclaration(Symbol),
-    Assignment(Symbol, Box<AstNode>),
-    FunctionCall(String, Vec<AstNode>),
+    VariableDeclaration(Symbol, Option<Box<AstNode>>, Span),
+    GlobalDeclaration(Symbol, Option<Box<AstNode>>),
+    Assignment(Symbol, Box<AstNode>, Span),
+    FunctionCall(Symbol, Vec<AstNode>),
+    Printf(Vec<AstNode>),
+    /// The builtin assert, which traps when its condition is false
+    Assert(Box<AstNode>),
+    StringLiteral(String),
     Widen(PrimitiveType, Box<AstNode>),
+    Cast(PrimitiveType, Box<AstNode>),
+    Ternary(Box<AstNode>, Box<AstNode>, Box<AstNode>),
+    AddressOf(Symbol),
+    Dereference(Box<AstNode>),
+    DereferenceAssignment(Box<AstNode>, Box<AstNode>),
+    Index(Symbol, Box<AstNode>),
+    IndexAssignment(Symbol, Box<AstNode>, Box<AstNode>),
     Identifier(Symbol),
     Function(Symbol, Box<AstNode>),
+    ExternFunction(Symbol),
     If(Box<AstNode>, Box<AstNode>, Option<Box<AstNode>>),
     While(Box<AstNode>, Box<AstNode>),
-    Block(Vec<AstNode>),
+    For(Box<AstNode>, Box<AstNode>, Box<AstNode>, Box<AstNode>),
+    Return(Option<Box<AstNode>>),
+    Break(Span),
+    Continue(Span),
+    /// A block of statements, with the expression which is its value when the
+    /// block is used as an expression
+    Block(Vec<AstNode>, Option<Box<AstNode>>),
 }
 
 impl AstNode {
     pub fn print(&self, indentation: usize) {
         match self {
-            AstNode::BinaryOperation(op_type, left, right) => {
+            AstNode::BinaryOperation(op_type, left, right, _) => {
                 println!("{}{:?}", " ".repeat(indentation), op_type);
                 left.print(indentation + 2);
                 right.print(indentation + 2);
             }
+            AstNode::UnaryOperation(op_type, node) => {
+                println!("{}{:?}", " ".repeat(indentation), op_type);
+                node.print(indentation + 2);
+            }
+            AstNode::NumericLiteral(PrimitiveType::Bool, value) => {
+                println!(
+                    "{}Bool: {}",
+                    " ".repeat(indentation),
+                    value.as_u64(&PrimitiveType::Bool) != 0
+                );
+            }
+            AstNode::NumericLiteral(PrimitiveType::Char, value) => {
+                println!(
+                    "{}Char: {:?}",
+                    " ".repeat(indentation),
+                    value.as_u64(&PrimitiveType::Char) as u8 as char
+                );
+            }
+            AstNode::NumericLiteral(PrimitiveType::Float32, value) => {
+                println!(
+                    "{}Float32: {:?}",
+                    " ".repeat(indentation),
+                    value.as_f64(&PrimitiveType::Float32) as f32
+                );
+            }
+            AstNode::NumericLiteral(PrimitiveType::Float64, value) => {
+                println!(
+                    "{}Float64: {:?}",
+                    " ".repeat(indentation),
+                    value.as_f64(&PrimitiveType::Float64)
+                );
+            }
+            AstNode::NumericLiteral(primitive_type, value) if primitive_type.is_signed() => {
+                println!(
+                    "{}{:?}: {:?}",
+                    " ".repeat(indentation),
+                    primitive_type,
+                    value.as_u64(primitive_type) as i64
+                );
+            }
             AstNode::NumericLiteral(primitive_type, value) => {
                 println!(
                     "{}{:?}: {:?}",
                     " ".repeat(indentation),
                     primitive_type,
-                    unsafe { value.uint32 }
+                    value.as_u64(primitive_type)
                 );
             }
-            AstNode::Block(children) => {
+            AstNode::Block(children, result) => {
                 println!("{}Block", " ".repeat(indentation));
                 for child in children {
                     child.print(indentation + 2);
                 }
+                if let Some(result) = result {
+                    println!("{}Result", " ".repeat(indentation));
+                    result.print(indentation + 2);
+                }
             }
-            AstNode::VariableDeclaration(var) => {
+            AstNode::VariableDeclaration(var, initializer, _) => {
                 println!(
                     "{}Var {}: {:?}",
                     " ".repeat(indentation),
                     var.name,
                     var.primitive_type
                 );
+                if let Some(initializer) = initializer {
+                    initializer.print(indentation + 2);
+                }
+            }
+            AstNode::GlobalDeclaration(var, initializer) => {
+                println!(
+                    "{}Global {}: {:?}",
+                    " ".repeat(indentation),
+                    var.name,
+                    var.primitive_type
+                );
+                if let Some(initializer) = initializer {
+                    initializer.print(indentation + 2);
+                }
             }
-            AstNode::Assignment(var, node) => {
+            AstNode::Assignment(var, node, _) => {
                 println!("{}{} =", " ".repeat(indentation), var.name);
                 node.print(indentation + 2);
             }
-            AstNode::FunctionCall(name, params) => {
-                println!("{}{}(", " ".repeat(indentation), name);
+            AstNode::FunctionCall(symbol, params) => {
+                println!("{}{}(", " ".repeat(indentation), symbol.name);
                 for param in params {
                     param.print(indentation + 2);
                 }
                 println!("{})", " ".repeat(indentation));
             }
+            AstNode::Printf(params) => {
+                println!("{}printf(", " ".repeat(indentation));
+                for param in params {
+                    param.print(indentation + 2);
+                }
+                println!("{})", " ".repeat(indentation));
+            }
+            AstNode::Assert(condition) => {
+                println!("{}assert(", " ".repeat(indentation));
+                condition.print(indentation + 2);
+                println!("{})", " ".repeat(indentation));
+            }
+            AstNode::StringLiteral(value) => {
+                println!("{}String: {:?}", " ".repeat(indentation), value);
+            }
             AstNode::Widen(primitive_type, node) => {
                 println!("{}Widen {:?}", " ".repeat(indentation), primitive_type);
                 node.print(indentation + 2);
             }
+            AstNode::Cast(primitive_type, node) => {
+                println!("{}Cast {:?}", " ".repeat(indentation), primitive_type);
+                node.print(indentation + 2);
+            }
+            AstNode::Ternary(condition, then_value, else_value) => {
+                println!("{}Ternary", " ".repeat(indentation));
+                condition.print(indentation + 2);
+                then_value.print(indentation + 2);
+                else_value.print(indentation + 2);
+            }
+            AstNode::AddressOf(var) => {
+                println!("{}&{}", " ".repeat(indentation), var.name);
+            }
+            AstNode::Dereference(node) => {
+                println!("{}Dereference", " ".repeat(indentation));
+                node.print(indentation + 2);
+            }
+            AstNode::DereferenceAssignment(pointer, expression) => {
+                println!("{}DereferenceAssignment", " ".repeat(indentation));
+                pointer.print(indentation + 2);
+                expression.print(indentation + 2);
+            }
+            AstNode::Index(var, index) => {
+                println!("{}{}[", " ".repeat(indentation), var.name);
+                index.print(indentation + 2);
+                println!("{}]", " ".repeat(indentation));
+            }
+            AstNode::IndexAssignment(var, index, expression) => {
+                println!("{}{}[", " ".repeat(indentation), var.name);
+                index.print(indentation + 2);
+                println!("{}] =", " ".repeat(indentation));
+                expression.print(indentation + 2);
+            }
             AstNode::Identifier(var) => {
                 println!("{}{}", " ".repeat(indentation), var.name);
             }
@@ -91,8 +233,7 @@ impl AstNode {
                 if let Some(else_code) = else_code {
                     println!("{}}} else {{", " ".repeat(indentation));
                     else_code.print(indentation + 2);
-
-                } 
+                }
                 println!("{}}}", " ".repeat(indentation));
             }
             AstNode::While(condition, code) => {
@@ -102,22 +243,176 @@ impl AstNode {
                 code.print(indentation + 2);
                 println!("{}}}", " ".repeat(indentation));
             }
+            AstNode::For(init, condition, update, code) => {
+                println!("{}For (", " ".repeat(indentation));
+                init.print(indentation + 2);
+                condition.print(indentation + 2);
+                update.print(indentation + 2);
+                println!("{}){{", " ".repeat(indentation));
+                code.print(indentation + 2);
+                println!("{}}}", " ".repeat(indentation));
+            }
             AstNode::Function(symbol, code) => {
                 println!("{}Fn {}", " ".repeat(indentation), symbol.name);
                 code.print(indentation + 2);
             }
+            AstNode::ExternFunction(symbol) => {
+                println!("{}Extern Fn {}", " ".repeat(indentation), symbol.name);
+            }
+            AstNode::Return(value) => {
+                println!("{}Return", " ".repeat(indentation));
+                if let Some(value) = value {
+                    value.print(indentation + 2);
+                }
+            }
+            AstNode::Break(_) => println!("{}Break", " ".repeat(indentation)),
+            AstNode::Continue(_) => println!("{}Continue", " ".repeat(indentation)),
+        }
+    }
+
+    /// Returns the amount of stack space in bytes needed for all variables
+    /// declared within this node.
+    pub fn get_stack_size(&self) -> i32 {
+        match self {
+            AstNode::VariableDeclaration(symbol, initializer, _) => symbol.offset.max(
+                initializer
+                    .as_ref()
+                    .map(|initializer| initializer.get_stack_size())
+                    .unwrap_or(0),
+            ),
+            AstNode::Block(children, result) => children
+                .iter()
+                .chain(result.as_deref())
+                .map(|child| child.get_stack_size())
+                .max()
+                .unwrap_or(0),
+            // Block expressions can declare variables inside of any expression
+            AstNode::Assignment(_, node, _)
+            | AstNode::UnaryOperation(_, node)
+            | AstNode::Widen(_, node)
+            | AstNode::Cast(_, node)
+            | AstNode::Assert(node)
+            | AstNode::Dereference(node)
+            | AstNode::Index(_, node)
+            | AstNode::Return(Some(node)) => node.get_stack_size(),
+            AstNode::BinaryOperation(_, left, right, _)
+            | AstNode::DereferenceAssignment(left, right)
+            | AstNode::IndexAssignment(_, left, right)
+            | AstNode::While(left, right) => left.get_stack_size().max(right.get_stack_size()),
+            AstNode::Ternary(condition, then_value, else_value) => condition
+                .get_stack_size()
+                .max(then_value.get_stack_size())
+                .max(else_value.get_stack_size()),
+            AstNode::FunctionCall(_, params) | AstNode::Printf(params) => params
+                .iter()
+                .map(|param| param.get_stack_size())
+                .max()
+                .unwrap_or(0),
+            AstNode::If(condition, code, else_code) => {
+                let else_size = else_code
+                    .as_ref()
+                    .map(|else_code| else_code.get_stack_size())
+                    .unwrap_or(0);
+
+                condition
+                    .get_stack_size()
+                    .max(code.get_stack_size())
+                    .max(else_size)
+            }
+            AstNode::For(init, condition, update, code) => init
+                .get_stack_size()
+                .max(condition.get_stack_size())
+                .max(update.get_stack_size())
+                .max(code.get_stack_size()),
+            // Globals live in the data section and functions get their own frame
+            AstNode::GlobalDeclaration(_, _)
+            | AstNode::Function(_, _)
+            | AstNode::ExternFunction(_)
+            | AstNode::NumericLiteral(_, _)
+            | AstNode::StringLiteral(_)
+            | AstNode::AddressOf(_)
+            | AstNode::Identifier(_)
+            | AstNode::Return(None)
+            | AstNode::Break(_)
+            | AstNode::Continue(_) => 0,
+        }
+    }
+
+    /// Checks whether every path through this node ends in a return statement
+    ///
+    /// A `while true` loop without a break never finishes, so the code after
+    /// it can't be reached either.
+    pub fn always_returns(&self) -> bool {
+        match self {
+            AstNode::Return(_) => true,
+            AstNode::Block(children, _) => children.iter().any(|child| child.always_returns()),
+            AstNode::If(_, code, Some(else_code)) => {
+                code.always_returns() && else_code.always_returns()
+            }
+            AstNode::While(condition, code) => {
+                matches!(**condition, AstNode::NumericLiteral(PrimitiveType::Bool, ref value)
+                    if value.as_u64(&PrimitiveType::Bool) != 0)
+                    && !code.contains_break()
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks whether the statement after this node can never be reached,
+    /// because every path through it returns or leaves the enclosing loop
+    pub fn diverges(&self) -> bool {
+        match self {
+            AstNode::Break(_) | AstNode::Continue(_) => true,
+            AstNode::Block(children, _) => children.iter().any(|child| child.diverges()),
+            AstNode::If(_, code, Some(else_code)) => code.diverges() && else_code.diverges(),
+            node => node.always_returns(),
+        }
+    }
+
+    /// Checks whether this node contains a break out of the enclosing loop
+    fn contains_break(&self) -> bool {
+        match self {
+            AstNode::Break(_) => true,
+            AstNode::Block(children, _) => children.iter().any(|child| child.contains_break()),
+            AstNode::If(_, code, else_code) => {
+                code.contains_break()
+                    || else_code
+                        .as_ref()
+                        .is_some_and(|else_code| else_code.contains_break())
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the bits of a constant initializer as stored in a value of the
+    /// given type, or `None` when the value is only known at runtime.
+    pub fn get_constant_bits(&self, primitive_type: &PrimitiveType) -> Option<u64> {
+        match self {
+            AstNode::Widen(_, node) => node.get_constant_bits(primitive_type),
+            AstNode::UnaryOperation(UnaryOperationType::Negate, node) => node
+                .get_constant_bits(primitive_type)
+                .map(|value| value.wrapping_neg()),
+            AstNode::NumericLiteral(PrimitiveType::Float32, value)
+                if *primitive_type == PrimitiveType::Float64 =>
+            {
+                Some(value.as_f64(&PrimitiveType::Float32).to_bits())
+            }
+            AstNode::NumericLiteral(literal_type, value) => Some(value.as_u64(literal_type)),
+            _ => None,
         }
     }
 
     pub fn get_primitive_type(&self) -> PrimitiveType {
         match self {
-            AstNode::BinaryOperation(op_type, left, right) => match op_type {
+            AstNode::BinaryOperation(op_type, left, right, _) => match op_type {
                 BinaryOperationType::Equals
                 | BinaryOperationType::NotEquals
                 | BinaryOperationType::LessThan
                 | BinaryOperationType::LessThanOrEqual
                 | BinaryOperationType::GreaterThan
-                | BinaryOperationType::GreaterThanOrEqual => PrimitiveType::Bool,
+                | BinaryOperationType::GreaterThanOrEqual
+                | BinaryOperationType::LogicalAnd
+                | BinaryOperationType::LogicalOr => PrimitiveType::Bool,
                 _ => {
                     let left_type = left.get_primitive_type();
                     let right_type = right.get_primitive_type();
@@ -129,13 +424,43 @@ impl AstNode {
                     }
                 }
             },
-            AstNode::NumericLiteral(primitive_type, _) => *primitive_type,
-            AstNode::Widen(primitive_type, _) => *primitive_type,
-            AstNode::Identifier(symbol) => symbol.primitive_type,
+            AstNode::UnaryOperation(op_type, node) => match op_type {
+                UnaryOperationType::Negate => {
+                    let primitive_type = node.get_primitive_type();
+
+                    if primitive_type.is_unsigned() {
+                        primitive_type.switch_sign()
+                    } else {
+                        primitive_type
+                    }
+                }
+                UnaryOperationType::Not => PrimitiveType::Bool,
+                UnaryOperationType::BitNot => node.get_primitive_type(),
+            },
+            AstNode::NumericLiteral(primitive_type, _) => primitive_type.clone(),
+            AstNode::Widen(primitive_type, _) => primitive_type.clone(),
+            AstNode::Cast(primitive_type, _) => primitive_type.clone(),
+            AstNode::Ternary(_, then_value, _) => then_value.get_primitive_type(),
+            AstNode::AddressOf(symbol) => {
+                PrimitiveType::Pointer(Box::new(symbol.primitive_type.clone()))
+            }
+            AstNode::Index(symbol, _) => match &symbol.primitive_type {
+                PrimitiveType::Array(element_type, _) => *element_type.clone(),
+                _ => PrimitiveType::Unknown,
+            },
+            AstNode::Dereference(node) => match node.get_primitive_type() {
+                PrimitiveType::Pointer(primitive_type) => *primitive_type,
+                _ => PrimitiveType::Unknown,
+            },
+            AstNode::Identifier(symbol) => symbol.primitive_type.clone(),
+            AstNode::Assignment(symbol, _, _) => symbol.primitive_type.clone(),
+            AstNode::FunctionCall(symbol, _) => symbol.primitive_type.clone(),
+            AstNode::Block(_, Some(result)) => result.get_primitive_type(),
+            AstNode::StringLiteral(_) => PrimitiveType::Pointer(Box::new(PrimitiveType::Char)),
             _ => {
                 println!("WARNING: get_primitive_type called for unknown AstNode type!");
                 PrimitiveType::Unknown
-            },
+            }
         }
     }
 }
@@ -0,0 +1,44 @@
+use std::fmt;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(line: usize, col: usize, message: &str) -> Self {
+        CompileError {
+            line,
+            col,
+            message: message.to_string(),
+        }
+    }
+
+    /// Formats the error followed by the offending source line and a caret
+    /// under the reported column.
+    pub fn format_with_source(&self, source: &str) -> String {
+        let mut result = self.to_string();
+
+        if let Some(line) = source.lines().nth(self.line.saturating_sub(1)) {
+            // Tabs are kept so the caret lines up no matter how wide they are displayed
+            let padding: String = line
+                .graphemes(true)
+                .take(self.col.saturating_sub(1))
+                .map(|grapheme| if grapheme == "\t" { '\t' } else { ' ' })
+                .collect();
+
+            result.push_str(&format!("\n{}\n{}^", line, padding));
+        }
+
+        result
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}:{}\n{}", self.line, self.col, self.message)
+    }
+}
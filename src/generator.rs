@@ -1,69 +1,255 @@
 use crate::ast::*;
+use crate::error::*;
 use crate::scope::*;
 use crate::types::*;
 
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::panic;
+use std::rc::Rc;
+
+/// The payload the generator panics with when it reports an error, so these
+/// can be told apart from a panic caused by a bug in the generator
+pub enum GeneratorError {
+    /// An error at a known position in the source
+    Located(CompileError),
+    Unlocated(String),
+}
+
+/// Escapes a string so it can be used in an .asciz directive, every char of a
+/// string literal stands for a single byte
+pub fn escape_string(value: &str) -> String {
+    let mut result = String::new();
+
+    for byte in value.chars().map(|c| c as u8) {
+        match byte {
+            b'"' => result.push_str("\\\""),
+            b'\\' => result.push_str("\\\\"),
+            b'\n' => result.push_str("\\n"),
+            b'\t' => result.push_str("\\t"),
+            0x20..=0x7e => result.push(byte as char),
+            _ => result.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+
+    result
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Register {
     pub size: i32,
     pub index: usize,
+    /// Whether the register belongs to the floating point register file
+    pub float: bool,
+}
+
+/// The registers of a register file which are currently in use, every backend
+/// sizes the file to its own pool
+pub type RegisterFile = Rc<RefCell<Vec<Option<Register>>>>;
+
+/// An allocated register, which is freed again when the guard is dropped.
+///
+/// Instructions take ownership of the registers they consume and return the
+/// register holding their result, so a register can't be freed twice or leak.
+pub struct RegisterGuard {
+    register: Register,
+    register_file: RegisterFile,
+}
+
+impl RegisterGuard {
+    pub fn new(register: Register, register_file: RegisterFile) -> Self {
+        register_file.borrow_mut()[register.index] = Some(register);
+
+        RegisterGuard {
+            register,
+            register_file,
+        }
+    }
+
+    /// Reinterprets the register as holding a value of another size
+    pub fn with_size(mut self, size: i32) -> Self {
+        self.register.size = size;
+        self
+    }
+}
+
+impl Deref for RegisterGuard {
+    type Target = Register;
+
+    fn deref(&self) -> &Register {
+        &self.register
+    }
+}
+
+impl Drop for RegisterGuard {
+    fn drop(&mut self) {
+        self.register_file.borrow_mut()[self.register.index] = None;
+    }
 }
 
 pub trait CodeGenerator {
-    fn new(output_path: &str) -> Self;
+    fn new(output_path: &str, verbose: bool, optimization_level: u32) -> Self;
     fn write(&mut self, data: &str);
 
-    fn get_label(&mut self) -> i32;
+    /// Writes the generated code to its destination once generation is done
+    fn finish(&mut self) {}
+
+    /// Returns a new label, numbered separately for every prefix
+    fn get_named_label(&mut self, prefix: &'static str) -> String;
 
-    fn get_register(&mut self, size: i32) -> Register;
-    fn free_register(&mut self, reg: Register);
+    fn get_register(&mut self, size: i32) -> RegisterGuard;
+    fn get_float_register(&mut self, size: i32) -> RegisterGuard;
 
-    fn gen_assignment_instr(&mut self, variable: &Symbol, register: Register, size_index: usize);
+    fn gen_assignment_instr(
+        &mut self,
+        variable: &Symbol,
+        register: RegisterGuard,
+        size_index: usize,
+    );
     fn gen_comparison_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-        comparison_type: &str,
-    ) -> Register;
+        comparison_type: BinaryOperationType,
+        signed: bool,
+    ) -> RegisterGuard;
     fn gen_add_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register;
+    ) -> RegisterGuard;
+    fn gen_float_add_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard;
     fn gen_subtract_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register;
+    ) -> RegisterGuard;
     fn gen_multiply_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register;
+        signed: bool,
+    ) -> RegisterGuard;
     fn gen_divide_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard;
+    fn gen_modulo_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard;
+
+    fn gen_bitand_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard;
+    fn gen_bitor_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard;
+    fn gen_bitxor_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard;
+    fn gen_shift_left_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register;
+    ) -> RegisterGuard;
+    fn gen_shift_right_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard;
+    fn gen_logical_instr(
+        &mut self,
+        left: &AstNode,
+        right: &AstNode,
+        operation_type: BinaryOperationType,
+    ) -> RegisterGuard;
+
+    fn gen_ternary_instr(
+        &mut self,
+        condition: &AstNode,
+        then_value: &AstNode,
+        else_value: &AstNode,
+    ) -> RegisterGuard;
+
+    fn gen_negate_instr(&mut self, register: RegisterGuard, size_index: usize) -> RegisterGuard;
+    fn gen_not_instr(&mut self, register: RegisterGuard) -> RegisterGuard;
+    fn gen_bitnot_instr(&mut self, register: RegisterGuard, size_index: usize) -> RegisterGuard;
 
     fn gen_numeric_literal_instr(
         &mut self,
         primitive_type: &PrimitiveType,
         primitive_value: &PrimitiveValue,
-    ) -> Register;
+    ) -> RegisterGuard;
     fn gen_widen_instr(
         &mut self,
-        register: Register,
+        register: RegisterGuard,
         primitive_type: &PrimitiveType,
         src_index: usize,
         dest_index: usize,
-    ) -> Register;
-    fn gen_identifier_instr(&mut self, symbol: &Symbol) -> Register;
-    fn gen_functioncall_instr(&mut self, name: &str, params: &[AstNode]);
+        signed: bool,
+    ) -> RegisterGuard;
+    fn gen_cast_instr(
+        &mut self,
+        register: RegisterGuard,
+        src_type: &PrimitiveType,
+        dest_type: &PrimitiveType,
+    ) -> RegisterGuard;
+    fn gen_identifier_instr(&mut self, symbol: &Symbol) -> RegisterGuard;
+    fn gen_address_of_instr(&mut self, symbol: &Symbol) -> RegisterGuard;
+    fn gen_dereference_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+    ) -> RegisterGuard;
+    fn gen_index_instr(&mut self, symbol: &Symbol, index_reg: RegisterGuard) -> RegisterGuard;
+    fn gen_index_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        index_reg: RegisterGuard,
+        register: RegisterGuard,
+    );
+    fn gen_store_instr(
+        &mut self,
+        pointer_reg: RegisterGuard,
+        register: RegisterGuard,
+        size_index: usize,
+    );
+    fn gen_functioncall_instr(
+        &mut self,
+        symbol: &Symbol,
+        params: &[AstNode],
+    ) -> Option<RegisterGuard>;
+    fn gen_printf_instr(&mut self, params: &[AstNode]);
+    fn gen_assert_instr(&mut self, condition: &AstNode);
+    fn gen_string_literal_instr(&mut self, value: &str) -> RegisterGuard;
     fn gen_if_instr(
         &mut self,
         condition: &AstNode,
@@ -71,12 +257,35 @@ pub trait CodeGenerator {
         else_code: &Option<Box<AstNode>>,
     );
     fn gen_while_instr(&mut self, condition: &AstNode, code: &AstNode);
+    fn gen_for_instr(
+        &mut self,
+        init: &AstNode,
+        condition: &AstNode,
+        update: &AstNode,
+        code: &AstNode,
+    );
     fn gen_function_instr(&mut self, symbol: &Symbol, code: &AstNode);
+    fn gen_global_declaration_instr(&mut self, symbol: &Symbol, initializer: &Option<Box<AstNode>>);
+    fn gen_return_instr(&mut self, value: &Option<Box<AstNode>>);
+    fn gen_break_instr(&mut self, span: Span);
+    fn gen_continue_instr(&mut self, span: Span);
     fn do_post_check(&self) -> bool;
 
+    /// Generates definitions for the builtin functions so no runtime library
+    /// has to be linked in.
+    fn gen_runtime(&mut self);
+
+    /// Emits the string literals used by the program
+    fn gen_string_literals(&mut self);
+
     fn error(&self, message: &str) {
-        eprintln!("Generator error: {}", message);
-        panic!();
+        panic::panic_any(GeneratorError::Unlocated(message.to_string()));
+    }
+
+    fn error_at(&self, span: Span, message: &str) {
+        panic::panic_any(GeneratorError::Located(CompileError::new(
+            span.line, span.col, message,
+        )));
     }
 
     fn size_to_instruction_index(size: i32) -> usize {
@@ -98,91 +307,134 @@ pub trait CodeGenerator {
     fn gen_assignment(&mut self, variable: &Symbol, expression: &AstNode) {
         let reg = self.gen_expression(expression);
 
-        let expression_type = expression.get_primitive_type();
-
-        if !expression_type.is_compatible_with(&variable.primitive_type, true) {
-            self.error(&format!(
-                "Incompatible types in assignment, {:?} = {:?}",
-                variable.primitive_type, expression_type
-            ));
-        }
-
         let index = Self::size_to_instruction_index(variable.primitive_type.get_size());
-        self.gen_assignment_instr(&variable, reg, index);
-
-        self.free_register(reg);
-    }
-
-    fn gen_comparison(
-        &mut self,
-        left_reg: Register,
-        right_reg: Register,
-        index: usize,
-        comparison_type: &str,
-    ) -> Register {
-        self.gen_comparison_instr(left_reg, right_reg, index, comparison_type)
+        self.gen_assignment_instr(variable, reg, index);
     }
 
-    fn gen_expression(&mut self, expression: &AstNode) -> Register {
+    fn gen_expression(&mut self, expression: &AstNode) -> RegisterGuard {
         match expression {
-            AstNode::BinaryOperation(operation_type, left, right) => {
+            AstNode::BinaryOperation(
+                operation_type @ (BinaryOperationType::LogicalAnd | BinaryOperationType::LogicalOr),
+                left,
+                right,
+                _,
+            ) => self.gen_logical_instr(left, right, *operation_type),
+            AstNode::BinaryOperation(operation_type, left, right, _) => {
                 assert!(
                     left.get_primitive_type().get_size() == right.get_primitive_type().get_size()
                 );
 
-                assert!(!left.get_primitive_type().is_signed());
-                assert!(!right.get_primitive_type().is_signed());
-
                 let left_reg = self.gen_expression(left);
                 let right_reg = self.gen_expression(right);
                 let index = Self::size_to_instruction_index(left.get_primitive_type().get_size());
+                let signed = left.get_primitive_type().is_signed();
 
                 match operation_type {
+                    BinaryOperationType::Add if left.get_primitive_type().is_float() => {
+                        self.gen_float_add_instr(left_reg, right_reg, index)
+                    }
                     BinaryOperationType::Add => self.gen_add_instr(left_reg, right_reg, index),
                     BinaryOperationType::Subtract => {
                         self.gen_subtract_instr(left_reg, right_reg, index)
                     }
                     BinaryOperationType::Multiply => {
-                        self.gen_multiply_instr(left_reg, right_reg, index)
+                        self.gen_multiply_instr(left_reg, right_reg, index, signed)
                     }
                     BinaryOperationType::Divide => {
-                        self.gen_divide_instr(left_reg, right_reg, index)
+                        self.gen_divide_instr(left_reg, right_reg, index, signed)
                     }
-                    BinaryOperationType::Equals => {
-                        self.gen_comparison_instr(left_reg, right_reg, index, "sete")
+                    BinaryOperationType::Modulo => {
+                        self.gen_modulo_instr(left_reg, right_reg, index, signed)
                     }
-                    BinaryOperationType::NotEquals => {
-                        self.gen_comparison_instr(left_reg, right_reg, index, "setne")
+                    BinaryOperationType::BitAnd => {
+                        self.gen_bitand_instr(left_reg, right_reg, index)
                     }
-                    BinaryOperationType::LessThan => {
-                        self.gen_comparison_instr(left_reg, right_reg, index, "setl")
+                    BinaryOperationType::BitOr => self.gen_bitor_instr(left_reg, right_reg, index),
+                    BinaryOperationType::BitXor => {
+                        self.gen_bitxor_instr(left_reg, right_reg, index)
                     }
-                    BinaryOperationType::LessThanOrEqual => {
-                        self.gen_comparison_instr(left_reg, right_reg, index, "setle")
+                    BinaryOperationType::ShiftLeft => {
+                        self.gen_shift_left_instr(left_reg, right_reg, index)
                     }
-                    BinaryOperationType::GreaterThan => {
-                        self.gen_comparison_instr(left_reg, right_reg, index, "setg")
+                    BinaryOperationType::ShiftRight => {
+                        self.gen_shift_right_instr(left_reg, right_reg, index, signed)
                     }
-                    BinaryOperationType::GreaterThanOrEqual => {
-                        self.gen_comparison_instr(left_reg, right_reg, index, "setge")
+                    BinaryOperationType::Equals
+                    | BinaryOperationType::NotEquals
+                    | BinaryOperationType::LessThan
+                    | BinaryOperationType::LessThanOrEqual
+                    | BinaryOperationType::GreaterThan
+                    | BinaryOperationType::GreaterThanOrEqual => self.gen_comparison_instr(
+                        left_reg,
+                        right_reg,
+                        index,
+                        *operation_type,
+                        signed,
+                    ),
+                    BinaryOperationType::LogicalAnd | BinaryOperationType::LogicalOr => {
+                        unreachable!()
                     }
                 }
             }
+            AstNode::UnaryOperation(operation_type, node) => {
+                let register = self.gen_expression(node);
+                let index = Self::size_to_instruction_index(node.get_primitive_type().get_size());
+
+                match operation_type {
+                    UnaryOperationType::Negate => self.gen_negate_instr(register, index),
+                    UnaryOperationType::Not => self.gen_not_instr(register),
+                    UnaryOperationType::BitNot => self.gen_bitnot_instr(register, index),
+                }
+            }
             AstNode::NumericLiteral(primitive_type, value) => {
                 self.gen_numeric_literal_instr(primitive_type, value)
             }
             AstNode::Widen(primitive_type, node) => {
                 let register = self.gen_expression(node);
 
-                assert!(primitive_type.is_unsigned());
-
                 let src_index =
                     Self::size_to_instruction_index(node.get_primitive_type().get_size());
                 let dst_index = Self::size_to_instruction_index(primitive_type.get_size());
+                let signed = node.get_primitive_type().is_signed();
 
-                self.gen_widen_instr(register, &primitive_type, src_index, dst_index)
+                self.gen_widen_instr(register, primitive_type, src_index, dst_index, signed)
+            }
+            AstNode::Cast(primitive_type, node) => {
+                let register = self.gen_expression(node);
+                self.gen_cast_instr(register, &node.get_primitive_type(), primitive_type)
+            }
+            AstNode::Ternary(condition, then_value, else_value) => {
+                self.gen_ternary_instr(condition, then_value, else_value)
             }
             AstNode::Identifier(symbol) => self.gen_identifier_instr(symbol),
+            // The value of an assignment is the value stored in the variable
+            AstNode::Assignment(symbol, expression, _) => {
+                self.gen_assignment(symbol, expression);
+                self.gen_identifier_instr(symbol)
+            }
+            AstNode::StringLiteral(value) => self.gen_string_literal_instr(value),
+            AstNode::Block(children, Some(result)) => {
+                self.gen_block(children);
+                self.gen_expression(result)
+            }
+            AstNode::AddressOf(symbol) => self.gen_address_of_instr(symbol),
+            AstNode::Index(symbol, index) => {
+                let index_reg = self.gen_expression(index);
+                self.gen_index_instr(symbol, index_reg)
+            }
+            AstNode::Dereference(node) => {
+                let register = self.gen_expression(node);
+                self.gen_dereference_instr(register, &expression.get_primitive_type())
+            }
+            AstNode::FunctionCall(symbol, params) => {
+                match self.gen_functioncall_instr(symbol, params) {
+                    Some(register) => register,
+                    None => {
+                        self.error("Void function call used in an expression");
+                        unreachable!();
+                    }
+                }
+            }
             _ => {
                 self.error("unsupported astnode in gen_expression");
                 unreachable!();
@@ -192,15 +444,56 @@ pub trait CodeGenerator {
 
     fn gen_node(&mut self, node: &AstNode) {
         match node {
-            AstNode::Block(children) => self.gen_block(children),
-            AstNode::VariableDeclaration(_) => {}
-            AstNode::Assignment(var, expression) => self.gen_assignment(var, expression),
-            AstNode::FunctionCall(name, params) => self.gen_functioncall_instr(name, params),
+            AstNode::Block(children, result) => {
+                self.gen_block(children);
+
+                if let Some(result) = result {
+                    self.gen_expression(result);
+                }
+            }
+            AstNode::VariableDeclaration(var, initializer, _) => {
+                if let Some(initializer) = initializer {
+                    self.gen_assignment(var, initializer);
+                }
+            }
+            AstNode::Assignment(var, expression, _) => self.gen_assignment(var, expression),
+            AstNode::IndexAssignment(symbol, index, expression) => {
+                let register = self.gen_expression(expression);
+                let index_reg = self.gen_expression(index);
+
+                self.gen_index_assignment_instr(symbol, index_reg, register);
+            }
+            AstNode::DereferenceAssignment(pointer, expression) => {
+                let register = self.gen_expression(expression);
+                let pointer_reg = self.gen_expression(pointer);
+                let index =
+                    Self::size_to_instruction_index(expression.get_primitive_type().get_size());
+
+                self.gen_store_instr(pointer_reg, register, index);
+            }
+            AstNode::FunctionCall(symbol, params) => {
+                self.gen_functioncall_instr(symbol, params);
+            }
+            AstNode::Printf(params) => {
+                self.gen_printf_instr(params);
+            }
+            AstNode::Assert(condition) => self.gen_assert_instr(condition),
             AstNode::If(condition, code, else_code) => {
                 self.gen_if_instr(condition, code, else_code)
             }
             AstNode::While(condition, code) => self.gen_while_instr(condition, code),
+            AstNode::For(init, condition, update, code) => {
+                self.gen_for_instr(init, condition, update, code)
+            }
             AstNode::Function(symbol, code) => self.gen_function_instr(symbol, code),
+            // External functions are provided by the linker
+            AstNode::ExternFunction(_) => {}
+            AstNode::GlobalDeclaration(symbol, initializer) => {
+                self.gen_global_declaration_instr(symbol, initializer)
+            }
+            AstNode::Return(value) => self.gen_return_instr(value),
+            AstNode::Break(span) => self.gen_break_instr(*span),
+            AstNode::Continue(span) => self.gen_continue_instr(*span),
             _ => {
                 self.error("Trying to generate assembly for unsupported ast node!");
                 unreachable!();
@@ -210,9 +503,9 @@ pub trait CodeGenerator {
 
     fn gen(&mut self, node: &AstNode) {
         self.write("\t.globl\tmain");
-        self.write("\t.type\tmain, @function");
 
         self.gen_node(node);
+        self.gen_string_literals();
 
         self.do_post_check();
     }
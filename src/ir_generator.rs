@@ -0,0 +1,751 @@
+use crate::ast::*;
+use crate::generator::*;
+use crate::scope::*;
+use crate::types::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+fn operation_to_operator(operation_type: BinaryOperationType) -> &'static str {
+    match operation_type {
+        BinaryOperationType::Add => "+",
+        BinaryOperationType::Subtract => "-",
+        BinaryOperationType::Multiply => "*",
+        BinaryOperationType::Divide => "/",
+        BinaryOperationType::Modulo => "%",
+        BinaryOperationType::BitAnd => "&",
+        BinaryOperationType::BitOr => "|",
+        BinaryOperationType::BitXor => "^",
+        BinaryOperationType::ShiftLeft => "<<",
+        BinaryOperationType::ShiftRight => ">>",
+        BinaryOperationType::Equals => "==",
+        BinaryOperationType::NotEquals => "!=",
+        BinaryOperationType::LessThan => "<",
+        BinaryOperationType::LessThanOrEqual => "<=",
+        BinaryOperationType::GreaterThan => ">",
+        BinaryOperationType::GreaterThanOrEqual => ">=",
+        BinaryOperationType::LogicalAnd => "&&",
+        BinaryOperationType::LogicalOr => "||",
+    }
+}
+
+/// Generates a textual three address code instead of assembly
+///
+/// Every value gets a new virtual register, like `t2 = t0 + t1`, so the output
+/// shows what the shared generator code asks for without any of the quirks of
+/// a real instruction set. The register files grow whenever all of their slots
+/// are in use, so there is no limit on the number of live values.
+pub struct IrCodeGenerator {
+    output_path: String,
+    /// The generated code, it is only written out when generation is finished
+    output: String,
+    registers: RegisterFile,
+    float_registers: RegisterFile,
+    /// The virtual register held by each slot of the register files
+    temporaries: Vec<usize>,
+    float_temporaries: Vec<usize>,
+    temporary_count: usize,
+    /// The number of labels which were created for every prefix
+    label_counts: HashMap<&'static str, i32>,
+    /// The continue and break labels of the loops surrounding the current node
+    loop_labels: Vec<(String, String)>,
+}
+
+impl IrCodeGenerator {
+    fn name(&self, register: &Register) -> String {
+        let temporaries = if register.float {
+            &self.float_temporaries
+        } else {
+            &self.temporaries
+        };
+
+        format!("t{}", temporaries[register.index])
+    }
+
+    /// Gives the slot of a register a new virtual register, for the result of
+    /// an operation which replaces one of its operands.
+    fn rename(&mut self, register: &Register) -> String {
+        let temporaries = if register.float {
+            &mut self.float_temporaries
+        } else {
+            &mut self.temporaries
+        };
+
+        temporaries[register.index] = self.temporary_count;
+        self.temporary_count += 1;
+
+        self.name(register)
+    }
+
+    fn allocate(&mut self, size: i32, float: bool) -> RegisterGuard {
+        let (register_file, temporaries) = if float {
+            (&self.float_registers, &mut self.float_temporaries)
+        } else {
+            (&self.registers, &mut self.temporaries)
+        };
+
+        let free_index = register_file.borrow().iter().position(|r| r.is_none());
+        let index = match free_index {
+            Some(index) => index,
+            None => {
+                register_file.borrow_mut().push(None);
+                temporaries.push(0);
+                temporaries.len() - 1
+            }
+        };
+
+        let register =
+            RegisterGuard::new(Register { size, index, float }, Rc::clone(register_file));
+        self.rename(&register);
+
+        register
+    }
+
+    fn gen_binary(
+        &mut self,
+        operation_type: BinaryOperationType,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+    ) -> RegisterGuard {
+        let left = self.name(&left_reg);
+        let right = self.name(&right_reg);
+        let result = self.rename(&left_reg);
+
+        self.write(&format!(
+            "\t{} = {} {} {}",
+            result,
+            left,
+            operation_to_operator(operation_type),
+            right
+        ));
+
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_unary(&mut self, operator: &str, register: RegisterGuard) -> RegisterGuard {
+        let operand = self.name(&register);
+        let result = self.rename(&register);
+
+        self.write(&format!("\t{} = {}{}", result, operator, operand));
+
+        register
+    }
+
+    /// Converts a value into a new register of the given type
+    fn gen_conversion(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        let result_reg = self.allocate(primitive_type.get_size(), primitive_type.is_float());
+
+        self.write(&format!(
+            "\t{} = ({:?}) {}",
+            self.name(&result_reg),
+            primitive_type,
+            self.name(&register)
+        ));
+        drop(register);
+
+        result_reg
+    }
+
+    fn gen_condition_jump(&mut self, condition: &AstNode, label: &str) {
+        let condition_reg = self.gen_expression(condition);
+
+        self.write(&format!(
+            "\tif_false {} goto {}",
+            self.name(&condition_reg),
+            label
+        ));
+    }
+
+    /// Passes the arguments of a call, returning the call itself
+    fn gen_call(&mut self, name: &str, params: &[AstNode]) -> String {
+        for param in params {
+            let register = self.gen_expression(param);
+            self.write(&format!("\tparam {}", self.name(&register)));
+        }
+
+        format!("call {}, {}", name, params.len())
+    }
+}
+
+impl CodeGenerator for IrCodeGenerator {
+    fn new(output_path: &str, _verbose: bool, _optimization_level: u32) -> Self {
+        IrCodeGenerator {
+            output_path: output_path.to_string(),
+            output: String::new(),
+            registers: Rc::new(RefCell::new(Vec::new())),
+            float_registers: Rc::new(RefCell::new(Vec::new())),
+            temporaries: Vec::new(),
+            float_temporaries: Vec::new(),
+            temporary_count: 0,
+            label_counts: HashMap::new(),
+            loop_labels: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, data: &str) {
+        self.output.push_str(data);
+        self.output.push('\n');
+    }
+
+    fn finish(&mut self) {
+        // An output path of - writes the code to stdout
+        let mut file: Box<dyn Write> = if self.output_path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(&self.output_path).expect("Failed to create output file"))
+        };
+
+        file.write_all(self.output.as_bytes())
+            .expect("Failed to write to output file");
+    }
+
+    fn get_named_label(&mut self, prefix: &'static str) -> String {
+        let count = self.label_counts.entry(prefix).or_insert(0);
+        let label = format!(".L{}_{}", prefix, count);
+        *count += 1;
+        label
+    }
+
+    fn get_register(&mut self, size: i32) -> RegisterGuard {
+        self.allocate(size, false)
+    }
+
+    fn get_float_register(&mut self, size: i32) -> RegisterGuard {
+        self.allocate(size, true)
+    }
+
+    fn gen_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        register: RegisterGuard,
+        _size_index: usize,
+    ) {
+        self.write(&format!("\t{} = {}", symbol.name, self.name(&register)));
+    }
+
+    fn gen_comparison_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+        comparison_type: BinaryOperationType,
+        _signed: bool,
+    ) -> RegisterGuard {
+        self.gen_binary(comparison_type, left_reg, right_reg)
+    }
+
+    fn gen_add_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::Add, left_reg, right_reg)
+    }
+
+    fn gen_float_add_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::Add, left_reg, right_reg)
+    }
+
+    fn gen_subtract_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::Subtract, left_reg, right_reg)
+    }
+
+    fn gen_multiply_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+        _signed: bool,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::Multiply, left_reg, right_reg)
+    }
+
+    fn gen_divide_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+        _signed: bool,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::Divide, left_reg, right_reg)
+    }
+
+    fn gen_modulo_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+        _signed: bool,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::Modulo, left_reg, right_reg)
+    }
+
+    fn gen_bitand_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::BitAnd, left_reg, right_reg)
+    }
+
+    fn gen_bitor_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::BitOr, left_reg, right_reg)
+    }
+
+    fn gen_bitxor_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::BitXor, left_reg, right_reg)
+    }
+
+    fn gen_shift_left_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::ShiftLeft, left_reg, right_reg)
+    }
+
+    fn gen_shift_right_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        _size_index: usize,
+        _signed: bool,
+    ) -> RegisterGuard {
+        self.gen_binary(BinaryOperationType::ShiftRight, left_reg, right_reg)
+    }
+
+    fn gen_logical_instr(
+        &mut self,
+        left: &AstNode,
+        right: &AstNode,
+        operation_type: BinaryOperationType,
+    ) -> RegisterGuard {
+        let end_label = self.get_named_label("logical_end");
+
+        let left_reg = self.gen_expression(left);
+
+        // Skip the right operand when the left one already determines the result
+        let jump = match operation_type {
+            BinaryOperationType::LogicalAnd => "if_false",
+            BinaryOperationType::LogicalOr => "if_true",
+            _ => {
+                self.error("Trying to generate a logical instruction for a non logical operator");
+                unreachable!();
+            }
+        };
+        self.write(&format!(
+            "\t{} {} goto {}",
+            jump,
+            self.name(&left_reg),
+            end_label
+        ));
+
+        let right_reg = self.gen_expression(right);
+        self.write(&format!(
+            "\t{} = {}",
+            self.name(&left_reg),
+            self.name(&right_reg)
+        ));
+        drop(right_reg);
+
+        self.write(&format!("{}:", end_label));
+
+        left_reg
+    }
+
+    fn gen_ternary_instr(
+        &mut self,
+        condition: &AstNode,
+        then_value: &AstNode,
+        else_value: &AstNode,
+    ) -> RegisterGuard {
+        let else_label = self.get_named_label("ternary_else");
+        let end_label = self.get_named_label("ternary_end");
+
+        self.gen_condition_jump(condition, &else_label);
+
+        // Both branches assign their value to the same result register
+        let primitive_type = then_value.get_primitive_type();
+        let result_reg = self.allocate(primitive_type.get_size(), primitive_type.is_float());
+
+        let then_reg = self.gen_expression(then_value);
+        self.write(&format!(
+            "\t{} = {}",
+            self.name(&result_reg),
+            self.name(&then_reg)
+        ));
+        drop(then_reg);
+        self.write(&format!("\tgoto {}", end_label));
+
+        self.write(&format!("{}:", else_label));
+        let else_reg = self.gen_expression(else_value);
+        self.write(&format!(
+            "\t{} = {}",
+            self.name(&result_reg),
+            self.name(&else_reg)
+        ));
+        drop(else_reg);
+
+        self.write(&format!("{}:", end_label));
+
+        result_reg
+    }
+
+    fn gen_negate_instr(&mut self, register: RegisterGuard, _size_index: usize) -> RegisterGuard {
+        self.gen_unary("-", register)
+    }
+
+    fn gen_not_instr(&mut self, register: RegisterGuard) -> RegisterGuard {
+        self.gen_unary("!", register)
+    }
+
+    fn gen_bitnot_instr(&mut self, register: RegisterGuard, _size_index: usize) -> RegisterGuard {
+        self.gen_unary("~", register)
+    }
+
+    fn gen_numeric_literal_instr(
+        &mut self,
+        primitive_type: &PrimitiveType,
+        primitive_value: &PrimitiveValue,
+    ) -> RegisterGuard {
+        let value = match primitive_type {
+            PrimitiveType::Bool => (primitive_value.as_u64(primitive_type) != 0).to_string(),
+            _ if primitive_type.is_float() => primitive_value.as_f64(primitive_type).to_string(),
+            _ if primitive_type.is_signed() => {
+                (primitive_value.as_u64(primitive_type) as i64).to_string()
+            }
+            _ => primitive_value.as_u64(primitive_type).to_string(),
+        };
+
+        let register = self.allocate(primitive_type.get_size(), primitive_type.is_float());
+        self.write(&format!("\t{} = {}", self.name(&register), value));
+
+        register
+    }
+
+    fn gen_widen_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+        _src_index: usize,
+        _dest_index: usize,
+        _signed: bool,
+    ) -> RegisterGuard {
+        self.gen_conversion(register, primitive_type)
+    }
+
+    fn gen_cast_instr(
+        &mut self,
+        register: RegisterGuard,
+        _src_type: &PrimitiveType,
+        dest_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        self.gen_conversion(register, dest_type)
+    }
+
+    fn gen_identifier_instr(&mut self, symbol: &Symbol) -> RegisterGuard {
+        let primitive_type = &symbol.primitive_type;
+        let register = self.allocate(primitive_type.get_size(), primitive_type.is_float());
+
+        self.write(&format!("\t{} = {}", self.name(&register), symbol.name));
+
+        register
+    }
+
+    fn gen_address_of_instr(&mut self, symbol: &Symbol) -> RegisterGuard {
+        let register = self.get_register(64);
+
+        self.write(&format!("\t{} = &{}", self.name(&register), symbol.name));
+
+        register
+    }
+
+    fn gen_dereference_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        let result_reg = self.allocate(primitive_type.get_size(), primitive_type.is_float());
+
+        self.write(&format!(
+            "\t{} = *{}",
+            self.name(&result_reg),
+            self.name(&register)
+        ));
+        drop(register);
+
+        result_reg
+    }
+
+    fn gen_index_instr(&mut self, symbol: &Symbol, index_reg: RegisterGuard) -> RegisterGuard {
+        let element_type = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type.as_ref().clone(),
+            _ => {
+                self.error("Trying to index a symbol which is not an array");
+                unreachable!();
+            }
+        };
+        let register = self.allocate(element_type.get_size(), element_type.is_float());
+
+        self.write(&format!(
+            "\t{} = {}[{}]",
+            self.name(&register),
+            symbol.name,
+            self.name(&index_reg)
+        ));
+
+        register
+    }
+
+    fn gen_index_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        index_reg: RegisterGuard,
+        register: RegisterGuard,
+    ) {
+        self.write(&format!(
+            "\t{}[{}] = {}",
+            symbol.name,
+            self.name(&index_reg),
+            self.name(&register)
+        ));
+    }
+
+    fn gen_store_instr(
+        &mut self,
+        pointer_reg: RegisterGuard,
+        register: RegisterGuard,
+        _size_index: usize,
+    ) {
+        self.write(&format!(
+            "\t*{} = {}",
+            self.name(&pointer_reg),
+            self.name(&register)
+        ));
+    }
+
+    fn gen_functioncall_instr(
+        &mut self,
+        symbol: &Symbol,
+        params: &[AstNode],
+    ) -> Option<RegisterGuard> {
+        let call = self.gen_call(&symbol.name, params);
+
+        if symbol.primitive_type == PrimitiveType::Void {
+            self.write(&format!("\t{}", call));
+            return None;
+        }
+
+        let primitive_type = &symbol.primitive_type;
+        let register = self.allocate(primitive_type.get_size(), primitive_type.is_float());
+        self.write(&format!("\t{} = {}", self.name(&register), call));
+
+        Some(register)
+    }
+
+    fn gen_printf_instr(&mut self, params: &[AstNode]) {
+        let call = self.gen_call("printf", params);
+        self.write(&format!("\t{}", call));
+    }
+
+    fn gen_assert_instr(&mut self, condition: &AstNode) {
+        let condition_reg = self.gen_expression(condition);
+        let end_label = self.get_named_label("assert_end");
+
+        self.write(&format!(
+            "\tif_true {} goto {}",
+            self.name(&condition_reg),
+            end_label
+        ));
+        self.write("\ttrap");
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_string_literal_instr(&mut self, value: &str) -> RegisterGuard {
+        let register = self.get_register(64);
+
+        self.write(&format!(
+            "\t{} = \"{}\"",
+            self.name(&register),
+            escape_string(value)
+        ));
+
+        register
+    }
+
+    // String literals are written where they are used
+    fn gen_string_literals(&mut self) {}
+
+    fn gen_if_instr(
+        &mut self,
+        condition: &AstNode,
+        code: &AstNode,
+        else_code: &Option<Box<AstNode>>,
+    ) {
+        let else_label = self.get_named_label("if_else");
+        let end_label = self.get_named_label("if_end");
+
+        let has_else = else_code.is_some();
+        self.gen_condition_jump(condition, if has_else { &else_label } else { &end_label });
+
+        self.gen_node(code);
+        if let Some(else_code) = else_code {
+            self.write(&format!("\tgoto {}", end_label));
+            self.write(&format!("{}:", else_label));
+            self.gen_node(else_code);
+        }
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_while_instr(&mut self, condition: &AstNode, code: &AstNode) {
+        let start_label = self.get_named_label("while_start");
+        let end_label = self.get_named_label("while_end");
+
+        self.write(&format!("{}:", start_label));
+        self.gen_condition_jump(condition, &end_label);
+
+        self.loop_labels
+            .push((start_label.clone(), end_label.clone()));
+        self.gen_node(code);
+        self.loop_labels.pop();
+
+        self.write(&format!("\tgoto {}", start_label));
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_for_instr(
+        &mut self,
+        init: &AstNode,
+        condition: &AstNode,
+        update: &AstNode,
+        code: &AstNode,
+    ) {
+        self.gen_node(init);
+
+        let start_label = self.get_named_label("for_start");
+        let update_label = self.get_named_label("for_update");
+        let end_label = self.get_named_label("for_end");
+
+        self.write(&format!("{}:", start_label));
+        self.gen_condition_jump(condition, &end_label);
+
+        // A continue still has to run the update before the next iteration
+        self.loop_labels
+            .push((update_label.clone(), end_label.clone()));
+        self.gen_node(code);
+        self.loop_labels.pop();
+
+        self.write(&format!("{}:", update_label));
+        self.gen_node(update);
+
+        self.write(&format!("\tgoto {}", start_label));
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_function_instr(&mut self, symbol: &Symbol, code: &AstNode) {
+        assert!(symbol.symbol_type == SymbolType::Function);
+
+        self.write(&format!("function {}:", symbol.name));
+        self.gen_node(code);
+        self.write("end");
+    }
+
+    fn gen_global_declaration_instr(
+        &mut self,
+        symbol: &Symbol,
+        initializer: &Option<Box<AstNode>>,
+    ) {
+        match initializer
+            .as_ref()
+            .and_then(|initializer| initializer.get_constant_bits(&symbol.primitive_type))
+        {
+            Some(value) => self.write(&format!("global {} = {}", symbol.name, value as i64)),
+            None => self.write(&format!("global {}", symbol.name)),
+        }
+    }
+
+    fn gen_return_instr(&mut self, value: &Option<Box<AstNode>>) {
+        match value {
+            Some(value) => {
+                let register = self.gen_expression(value);
+                self.write(&format!("\treturn {}", self.name(&register)));
+            }
+            None => self.write("\treturn"),
+        }
+    }
+
+    fn gen_break_instr(&mut self, span: Span) {
+        let end_label = match self.loop_labels.last() {
+            Some((_, end_label)) => end_label.clone(),
+            None => {
+                self.error_at(span, "Break statement outside of a loop");
+                unreachable!();
+            }
+        };
+
+        self.write(&format!("\tgoto {}", end_label));
+    }
+
+    fn gen_continue_instr(&mut self, span: Span) {
+        let continue_label = match self.loop_labels.last() {
+            Some((continue_label, _)) => continue_label.clone(),
+            None => {
+                self.error_at(span, "Continue statement outside of a loop");
+                unreachable!();
+            }
+        };
+
+        self.write(&format!("\tgoto {}", continue_label));
+    }
+
+    // The builtin functions are only known by name in the IR
+    fn gen_runtime(&mut self) {}
+
+    fn do_post_check(&self) -> bool {
+        let registers = self.registers.borrow();
+        let float_registers = self.float_registers.borrow();
+        for register in registers.iter().chain(float_registers.iter()) {
+            if register.is_some() {
+                self.error("Not all registers were freed!");
+                return false;
+            }
+        }
+        true
+    }
+
+    fn gen(&mut self, node: &AstNode) {
+        self.gen_node(node);
+        self.do_post_check();
+    }
+}
@@ -1,13 +1,19 @@
+use crate::error::*;
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     IntLiteral,
+    FloatLiteral,
+    StringLiteral,
+    BoolLiteral,
+    CharLiteral,
 
     Plus,
     Minus,
     Star,
     Slash,
+    Percent,
 
     ExclamationMark,
 
@@ -18,15 +24,24 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
 
     SemiColon,
     Colon,
+    QuestionMark,
     Comma,
     Var,
     If,
     Else,
     While,
+    For,
+    Break,
+    Continue,
     Function,
+    Extern,
+    Return,
+    As,
     Type,
 
     DoubleEqualSign,
@@ -35,11 +50,22 @@ pub enum TokenType {
     GreaterThan,
     LessThanOrEqual,
     GreaterThanOrEqual,
+    ShiftLeft,
+    ShiftRight,
+
+    Ampersand,
+    DoubleAmpersand,
+    Pipe,
+    DoublePipe,
+    Caret,
+    Tilde,
 }
 
 #[derive(Debug)]
 pub struct Token {
     pub token_type: TokenType,
+    /// The text of the token, string and char literals hold one char for
+    /// every byte of their value so any byte can be written with `\x`
     pub value: String,
     pub col: usize,
     pub line: usize,
@@ -50,6 +76,7 @@ pub struct Lexer<'a> {
     index: usize,
     current_col: usize,
     current_line: usize,
+    errors: Vec<CompileError>,
 }
 
 fn is_whitespace(string: &str) -> bool {
@@ -68,6 +95,11 @@ fn is_numeric(string: &str) -> bool {
     string.chars().all(|x: char| x.is_numeric())
 }
 
+/// Identifiers can't start with a digit but may contain one after that
+fn is_identifier(string: &str) -> bool {
+    is_alphabetic(string) || is_numeric(string) || string == "_"
+}
+
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Lexer {
@@ -75,15 +107,16 @@ impl<'a> Lexer<'a> {
             index: 0,
             current_col: 1,
             current_line: 1,
+            errors: Vec::new(),
         }
     }
 
-    fn error(&self, message: &str) {
-        eprintln!(
-            "Lexer error at line {}:{}\n{}",
-            self.current_line, self.current_col, message
-        );
-        panic!();
+    fn error(&mut self, message: &str) {
+        self.error_at(self.current_line, self.current_col, message);
+    }
+
+    fn error_at(&mut self, line: usize, col: usize, message: &str) {
+        self.errors.push(CompileError::new(line, col, message));
     }
 
     fn eof(&mut self) -> bool {
@@ -91,10 +124,13 @@ impl<'a> Lexer<'a> {
     }
 
     fn peek(&self, index: usize) -> String {
-        self.data[self.index + index].to_owned()
+        match self.data.get(self.index + index) {
+            Some(x) => (*x).to_owned(),
+            None => String::default(),
+        }
     }
 
-    fn consume(&mut self) -> &str {
+    fn consume(&mut self) -> &'a str {
         let result = self.data[self.index];
         self.index += 1;
 
@@ -130,21 +166,236 @@ impl<'a> Lexer<'a> {
         self.consume_while(|c| is_whitespace(c) || is_newline(c));
     }
 
+    fn skip_block_comment(&mut self) {
+        let line = self.current_line;
+        let mut depth = 0;
+
+        loop {
+            if self.eof() {
+                self.error(&format!(
+                    "Unterminated block comment starting at line {}",
+                    line
+                ));
+                break;
+            }
+
+            if self.peek(0) == "/" && self.peek(1) == "*" {
+                self.consume();
+                self.consume();
+                depth += 1;
+            } else if self.peek(0) == "*" && self.peek(1) == "/" {
+                self.consume();
+                self.consume();
+                depth -= 1;
+
+                if depth == 0 {
+                    break;
+                }
+            } else {
+                self.consume();
+            }
+        }
+    }
+
     fn tokenize_single_char(&mut self, token_type: TokenType) -> Token {
+        let line = self.current_line;
+        let col = self.current_col;
+
         let value = String::from(self.consume());
         Token {
-            line: self.current_line,
-            col: self.current_col - value.len(),
+            line,
+            col,
             token_type,
             value,
         }
     }
 
     fn tokenize_multichar(&mut self, condition: fn(&str) -> bool, token_type: TokenType) -> Token {
+        let line = self.current_line;
+        let col = self.current_col;
+
         let value = self.consume_while(condition);
         Token {
-            line: self.current_line,
-            col: self.current_col - value.len(),
+            line,
+            col,
+            token_type,
+            value,
+        }
+    }
+
+    fn tokenize_number(&mut self) -> Token {
+        let mut token =
+            self.tokenize_multichar(|c| is_numeric(c) || c == "_", TokenType::IntLiteral);
+
+        // A dot followed by a digit continues the literal as a float
+        if self.peek(0) == "." && is_numeric(&self.peek(1)) {
+            self.consume();
+            let fraction = self.consume_while(|c| is_numeric(c) || c == "_");
+
+            token.token_type = TokenType::FloatLiteral;
+            token.value = format!("{}.{}", token.value, fraction);
+        }
+
+        // Letters directly after a number would otherwise start an identifier
+        if is_alphabetic(&self.peek(0)) {
+            let suffix = self.consume_while(is_identifier);
+            self.error_at(
+                token.line,
+                token.col,
+                &format!(
+                    "Invalid suffix on numeric literal: {}{}",
+                    token.value, suffix
+                ),
+            );
+        } else if token.value.ends_with('_') || token.value.contains("__") {
+            self.error_at(
+                token.line,
+                token.col,
+                &format!(
+                    "Invalid digit separator in integer literal: {}",
+                    token.value
+                ),
+            );
+        }
+
+        token
+    }
+
+    /// Parses the two hex digits of a `\x` escape into the char standing for
+    /// that byte
+    fn tokenize_hex_escape(&mut self) -> Option<char> {
+        let mut value = 0;
+
+        for _ in 0..2 {
+            let digit = self.peek(0);
+            let mut chars = digit.chars();
+
+            match (chars.next().and_then(|c| c.to_digit(16)), chars.next()) {
+                (Some(digit), None) => {
+                    self.consume();
+                    value = value * 16 + digit;
+                }
+                _ => {
+                    self.error("Expected two hex digits after \\x");
+                    return None;
+                }
+            }
+        }
+
+        char::from_u32(value)
+    }
+
+    fn tokenize_string(&mut self) -> Token {
+        let line = self.current_line;
+        let col = self.current_col;
+
+        self.consume();
+
+        let mut value = String::default();
+
+        loop {
+            // A backslash at the very end can't start an escape sequence either
+            if self.eof() || (self.peek(0) == "\\" && self.index + 1 == self.data.len()) {
+                self.error(&format!(
+                    "Unterminated string literal starting at line {}",
+                    line
+                ));
+                break;
+            }
+
+            match self.consume() {
+                "\"" => break,
+                "\\" => match self.consume() {
+                    "n" => value.push('\n'),
+                    "t" => value.push('\t'),
+                    "\\" => value.push('\\'),
+                    "\"" => value.push('"'),
+                    "x" => {
+                        if let Some(c) = self.tokenize_hex_escape() {
+                            value.push(c);
+                        }
+                    }
+                    x => self.error(&format!("Unknown escape sequence: \\{}", x)),
+                },
+                x => value.extend(x.bytes().map(char::from)),
+            }
+        }
+
+        Token {
+            line,
+            col,
+            token_type: TokenType::StringLiteral,
+            value,
+        }
+    }
+
+    fn tokenize_char(&mut self) -> Token {
+        let line = self.current_line;
+        let col = self.current_col;
+
+        self.consume();
+
+        let value = match self.peek(0).as_str() {
+            "" | "'" | "\n" => String::default(),
+            "\\" if self.index + 1 < self.data.len() => {
+                self.consume();
+                match self.consume() {
+                    "n" => String::from("\n"),
+                    "t" => String::from("\t"),
+                    "0" => String::from("\0"),
+                    "\\" => String::from("\\"),
+                    "'" => String::from("'"),
+                    "x" => self
+                        .tokenize_hex_escape()
+                        .map(String::from)
+                        .unwrap_or_else(|| String::from("x")),
+                    x => {
+                        self.error(&format!("Unknown escape sequence: \\{}", x));
+                        String::from(x)
+                    }
+                }
+            }
+            _ => self.consume().bytes().map(char::from).collect(),
+        };
+
+        if self.peek(0) == "'" {
+            self.consume();
+
+            if value.is_empty() {
+                self.error_at(line, col, "Empty character literal");
+            } else if value.chars().count() != 1 {
+                let bytes: Vec<u8> = value.chars().map(|c| c as u8).collect();
+                self.error_at(
+                    line,
+                    col,
+                    &format!(
+                        "Character literal doesn't fit in a byte: '{}'",
+                        String::from_utf8_lossy(&bytes)
+                    ),
+                );
+            }
+        } else {
+            self.error_at(line, col, "Unterminated character literal");
+        }
+
+        Token {
+            line,
+            col,
+            token_type: TokenType::CharLiteral,
+            value,
+        }
+    }
+
+    fn tokenize_double_char(&mut self, token_type: TokenType) -> Token {
+        let line = self.current_line;
+        let col = self.current_col;
+
+        let mut value = String::from(self.consume());
+        value.push_str(self.consume());
+
+        Token {
+            line,
+            col,
             token_type,
             value,
         }
@@ -156,23 +407,31 @@ impl<'a> Lexer<'a> {
             "else" => Some(TokenType::Else),
             "var" => Some(TokenType::Var),
             "while" => Some(TokenType::While),
+            "for" => Some(TokenType::For),
+            "break" => Some(TokenType::Break),
+            "continue" => Some(TokenType::Continue),
             "fn" => Some(TokenType::Function),
-            "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "bool" => {
-                Some(TokenType::Type)
-            }
+            "extern" => Some(TokenType::Extern),
+            "return" => Some(TokenType::Return),
+            "as" => Some(TokenType::As),
+            "true" | "false" => Some(TokenType::BoolLiteral),
+            "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "f32" | "f64"
+            | "bool" | "char" => Some(TokenType::Type),
             _ => None,
         }
     }
 
     fn tokenize_possible_keyword(&mut self) -> Token {
-        let value = self.consume_while(|c| is_alphabetic(c) || is_numeric(c));
+        let line = self.current_line;
+        let col = self.current_col;
+
+        let value = self.consume_while(is_identifier);
 
-        let token_type =
-            Self::keyword_to_tokentype(&value).unwrap_or(TokenType::Identifier);
+        let token_type = Self::keyword_to_tokentype(&value).unwrap_or(TokenType::Identifier);
 
         Token {
-            line: self.current_line,
-            col: self.current_col - value.len(),
+            line,
+            col,
             token_type,
             value,
         }
@@ -184,6 +443,9 @@ impl<'a> Lexer<'a> {
         multiple_type: TokenType,
         next_char: &str,
     ) -> Token {
+        let line = self.current_line;
+        let col = self.current_col;
+
         let mut value = String::from(self.consume());
         let mut token_type = single_type;
 
@@ -193,44 +455,55 @@ impl<'a> Lexer<'a> {
         }
 
         Token {
-            line: self.current_line,
-            col: self.current_col - value.len(),
+            line,
+            col,
             token_type,
             value,
         }
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, Vec<CompileError>> {
         let mut result: Vec<Token> = Vec::new();
 
         while !self.eof() {
             self.skip_whitespace();
 
-            if self.eof() {
-                break;
+            // Line comments start with either # or //
+            if self.peek(0) == "#" || (self.peek(0) == "/" && self.peek(1) == "/") {
+                self.consume_while(|c| !is_newline(c));
+                continue;
             }
 
-            while self.peek(0) == "#" {
-                self.consume_while(|c| !is_newline(c));
-                self.consume();
-                self.skip_whitespace();
+            if self.peek(0) == "/" && self.peek(1) == "*" {
+                self.skip_block_comment();
+                continue;
+            }
+
+            if self.eof() {
+                break;
             }
 
             let current_char = self.peek(0);
 
             let token = match current_char.chars().next().unwrap() {
-                '0'..='9' => Some(self.tokenize_multichar(is_numeric, TokenType::IntLiteral)),
-                'a'..='z' | 'A'..='Z' => Some(self.tokenize_possible_keyword()),
+                '0'..='9' => Some(self.tokenize_number()),
+                'a'..='z' | 'A'..='Z' | '_' => Some(self.tokenize_possible_keyword()),
+                '"' => Some(self.tokenize_string()),
+                '\'' => Some(self.tokenize_char()),
                 '+' => Some(self.tokenize_single_char(TokenType::Plus)),
                 '-' => Some(self.tokenize_single_char(TokenType::Minus)),
                 '*' => Some(self.tokenize_single_char(TokenType::Star)),
                 '/' => Some(self.tokenize_single_char(TokenType::Slash)),
+                '%' => Some(self.tokenize_single_char(TokenType::Percent)),
                 '(' => Some(self.tokenize_single_char(TokenType::LeftParen)),
                 ')' => Some(self.tokenize_single_char(TokenType::RightParen)),
                 '{' => Some(self.tokenize_single_char(TokenType::LeftBrace)),
                 '}' => Some(self.tokenize_single_char(TokenType::RightBrace)),
+                '[' => Some(self.tokenize_single_char(TokenType::LeftBracket)),
+                ']' => Some(self.tokenize_single_char(TokenType::RightBracket)),
                 ';' => Some(self.tokenize_single_char(TokenType::SemiColon)),
                 ':' => Some(self.tokenize_single_char(TokenType::Colon)),
+                '?' => Some(self.tokenize_single_char(TokenType::QuestionMark)),
                 ',' => Some(self.tokenize_single_char(TokenType::Comma)),
                 '!' => Some(self.tokenize_possible_multichar(
                     TokenType::ExclamationMark,
@@ -242,6 +515,10 @@ impl<'a> Lexer<'a> {
                     TokenType::DoubleEqualSign,
                     "=",
                 )),
+                '<' if self.peek(1) == "<" => Some(self.tokenize_double_char(TokenType::ShiftLeft)),
+                '>' if self.peek(1) == ">" => {
+                    Some(self.tokenize_double_char(TokenType::ShiftRight))
+                }
                 '<' => Some(self.tokenize_possible_multichar(
                     TokenType::LessThan,
                     TokenType::LessThanOrEqual,
@@ -252,14 +529,34 @@ impl<'a> Lexer<'a> {
                     TokenType::GreaterThanOrEqual,
                     "=",
                 )),
+                '&' => Some(self.tokenize_possible_multichar(
+                    TokenType::Ampersand,
+                    TokenType::DoubleAmpersand,
+                    "&",
+                )),
+                '|' => Some(self.tokenize_possible_multichar(
+                    TokenType::Pipe,
+                    TokenType::DoublePipe,
+                    "|",
+                )),
+                '^' => Some(self.tokenize_single_char(TokenType::Caret)),
+                '~' => Some(self.tokenize_single_char(TokenType::Tilde)),
                 _ => None,
             };
 
             match token {
                 Some(x) => result.push(x),
-                None => self.error(&format!("Unexpected character: {}", current_char)),
+                None => {
+                    self.error(&format!("Unexpected character: {}", current_char));
+                    self.consume();
+                }
             }
         }
-        result
+
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+
+        Ok(result)
     }
 }
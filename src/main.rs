@@ -1,16 +1,95 @@
+mod arm64_generator;
+use arm64_generator::*;
 mod ast;
+mod error;
 mod lexer;
 use lexer::*;
+mod optimizer;
+use optimizer::*;
 mod parser;
 use parser::*;
 mod generator;
 use generator::*;
+mod ir_generator;
+use ir_generator::*;
 mod scope;
 mod types;
 mod x86_generator;
+mod x86_intel;
+mod x86_peephole;
 use x86_generator::*;
 
 use clap::{App, Arg};
+use std::io::{self, IsTerminal};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process::Command;
+
+/// The exit codes which tell which stage of the compiler failed
+const EXIT_LEXER_ERROR: i32 = 2;
+const EXIT_PARSER_ERROR: i32 = 3;
+const EXIT_GENERATOR_ERROR: i32 = 4;
+const EXIT_OPTIMIZER_ERROR: i32 = 5;
+
+/// Generates the assembly for a program and writes it out
+fn generate<G: CodeGenerator>(mut generator: G, node: &ast::AstNode, with_runtime: bool) {
+    generator.gen(node);
+    if with_runtime {
+        generator.gen_runtime();
+    }
+    generator.finish();
+}
+
+/// Prints the symbols of every scope as a table, sorted by name
+fn print_symbols(scopes: &[scope::Scope]) {
+    println!("===== Symbols =====");
+
+    for (depth, scope) in scopes.iter().enumerate() {
+        println!("Scope {}", depth);
+        println!(
+            "  {:<16} {:<18} {:<16} {:<24} Offset",
+            "Name", "Kind", "Type", "Parameters"
+        );
+
+        let mut symbols: Vec<&scope::Symbol> = scope.symbols.values().collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for symbol in symbols {
+            println!(
+                "  {:<16} {:<18} {:<16} {:<24} {}",
+                symbol.name,
+                format!("{:?}", symbol.symbol_type),
+                format!("{:?}", symbol.primitive_type),
+                format!("{:?}", symbol.parameter_types),
+                symbol.offset
+            );
+        }
+    }
+
+    println!();
+}
+
+/// Assembles the generated assembly and links it together with the runtime
+/// that defines the builtin print functions.
+fn build_executable(
+    assembly_file: &str,
+    runtime_file: Option<&str>,
+    executable: &str,
+    pie: bool,
+) -> Result<(), String> {
+    let status = Command::new("gcc")
+        .args(if pie { None } else { Some("-no-pie") })
+        .args(runtime_file)
+        .args([assembly_file, "-o", executable])
+        .status()
+        .map_err(|error| format!("Failed to run gcc: {}", error))?;
+
+    if !status.success() {
+        return Err(format!("gcc exited with {}", status));
+    }
+
+    Ok(())
+}
 
 fn main() {
     let matches = App::new("Compiler")
@@ -18,27 +97,309 @@ fn main() {
         .author("Hector Peeters")
         .arg(
             Arg::with_name("INPUT")
-                .help("Sets the input file to use")
-                .required(true)
+                .help("Sets the input file to use, - or no file reads the source from stdin")
                 .index(1),
         )
+        .arg(
+            Arg::with_name("emit")
+                .long("emit")
+                .help("Selects the compiler stage to output: tokens, ast, ir or asm")
+                .takes_value(true)
+                .possible_values(&["tokens", "ast", "ir", "asm"])
+                .default_value("asm"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .help("Sets the assembly output file, - writes it to stdout")
+                .takes_value(true)
+                .default_value("output.s"),
+        )
+        .arg(
+            Arg::with_name("build").short("b").long("build").help(
+                "Assembles and links the output into an executable named after the input file",
+            ),
+        )
+        .arg(
+            Arg::with_name("runtime")
+                .long("runtime")
+                .value_name("FILE")
+                .help("Sets the runtime source or object file that is linked into the executable")
+                .takes_value(true)
+                .default_value("lib.c"),
+        )
+        .arg(
+            Arg::with_name("with-runtime")
+                .long("with-runtime")
+                .help("Generates the builtin print functions instead of linking a runtime file"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .long("target")
+                .help("Selects the architecture to generate assembly for: x86-64 or aarch64")
+                .takes_value(true)
+                .possible_values(&["x86-64", "aarch64"])
+                .default_value("x86-64"),
+        )
+        .arg(
+            Arg::with_name("syntax")
+                .long("syntax")
+                .help("Selects the syntax of the generated assembly: att or intel")
+                .takes_value(true)
+                .possible_values(&["att", "intel"])
+                .default_value("att"),
+        )
+        .arg(
+            Arg::with_name("pie")
+                .long("pie")
+                .help("Generates position independent code, calls to functions outside of the program go through the PLT (default)"),
+        )
+        .arg(
+            Arg::with_name("no-pie")
+                .long("no-pie")
+                .conflicts_with("pie")
+                .help("Generates code for an executable which is loaded at a fixed address"),
+        )
+        .arg(
+            Arg::with_name("optimize")
+                .short("O")
+                .value_name("LEVEL")
+                .help("Sets the optimization level: 0 disables all optimizations, 1 enables constant folding, dead code elimination and the peephole optimizer")
+                .takes_value(true)
+                .possible_values(&["0", "1"])
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("overflow")
+                .long("overflow")
+                .help("Selects what happens when a constant expression overflows while folding it: wrap with a warning or error")
+                .takes_value(true)
+                .possible_values(&["wrap", "error"])
+                .default_value("wrap"),
+        )
+        .arg(
+            Arg::with_name("dump-symbols")
+                .long("dump-symbols")
+                .help("Prints the symbol table after parsing"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Prints the tokens, AST and assembly while generating assembly"),
+        )
         .get_matches();
 
-    let input_file = matches.value_of("INPUT").unwrap();
-    let input = std::fs::read_to_string(input_file).expect("Failed to read input file!");
+    let input_file = matches.value_of("INPUT").unwrap_or("-");
+    let output_file = matches.value_of("output").unwrap();
+    let emit = matches.value_of("emit").unwrap();
+    let syntax = match matches.value_of("syntax").unwrap() {
+        "intel" => AssemblySyntax::Intel,
+        _ => AssemblySyntax::Att,
+    };
+    let verbose = matches.is_present("verbose");
+    let dump_symbols = matches.is_present("dump-symbols");
+    let optimization_level: u32 = matches.value_of("optimize").unwrap().parse().unwrap();
+    let deny_overflow = matches.value_of("overflow").unwrap() == "error";
+    let pie = !matches.is_present("no-pie");
+    let build = matches.is_present("build");
+    let with_runtime = matches.is_present("with-runtime");
 
-    let tokens = Lexer::new(&input).tokenize();
+    if build && output_file == "-" {
+        eprintln!("Can't build an executable when writing the assembly to stdout");
+        std::process::exit(1);
+    }
+
+    let target = matches.value_of("target").unwrap();
 
-    println!("===== Tokens =====");
-    for token in &tokens {
-        println!("{:?}", token);
+    if target != "x86-64" && syntax == AssemblySyntax::Intel {
+        eprintln!("Intel syntax is only available for x86-64");
+        std::process::exit(1);
+    }
+    if target != "x86-64" && build {
+        eprintln!("Executables can only be built for x86-64");
+        std::process::exit(1);
     }
 
-    println!("\n===== AST =====");
-    let result_node = Parser::new(tokens).parse();
-    result_node.print(0);
+    let (input, source_name) = if input_file == "-" {
+        if io::stdin().is_terminal() {
+            eprintln!("No input file given and nothing was piped to stdin");
+            std::process::exit(1);
+        }
+
+        let input = io::read_to_string(io::stdin()).expect("Failed to read from stdin!");
+        (input, "<stdin>")
+    } else {
+        let input = std::fs::read_to_string(input_file).expect("Failed to read input file!");
+        (input, input_file)
+    };
+
+    let tokens = match Lexer::new(&input).tokenize() {
+        Ok(tokens) => tokens,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!(
+                    "Lexer error in {} at {}",
+                    source_name,
+                    error.format_with_source(&input)
+                );
+            }
+            std::process::exit(EXIT_LEXER_ERROR);
+        }
+    };
+
+    if emit == "tokens" || verbose {
+        println!("===== Tokens =====");
+        for token in &tokens {
+            println!("{:?}", token);
+        }
+        println!();
+    }
+
+    if emit == "tokens" {
+        return;
+    }
+
+    let mut parser = Parser::new(tokens);
+    let result = parser.parse();
+
+    for warning in parser.warnings() {
+        eprintln!(
+            "Warning in {} at {}",
+            source_name,
+            warning.format_with_source(&input)
+        );
+    }
+
+    if dump_symbols {
+        print_symbols(parser.scopes());
+    }
 
-    println!("\n===== Code Generation =====");
-    let mut generator = X86CodeGenerator::new("output.s");
-    generator.gen(&result_node);
+    let mut result_node = match result {
+        Ok(node) => node,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!(
+                    "Parser error in {} at {}",
+                    source_name,
+                    error.format_with_source(&input)
+                );
+            }
+            std::process::exit(EXIT_PARSER_ERROR);
+        }
+    };
+
+    if optimization_level >= 1 {
+        let mut folder = ConstantFolder::new(deny_overflow);
+        let result = folder.fold(result_node);
+
+        for warning in folder.warnings() {
+            eprintln!(
+                "Warning in {} at {}",
+                source_name,
+                warning.format_with_source(&input)
+            );
+        }
+
+        result_node = match result {
+            Ok(node) => eliminate_dead_code(node).expect("The program block is never removed"),
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!(
+                        "Optimizer error in {} at {}",
+                        source_name,
+                        error.format_with_source(&input)
+                    );
+                }
+                std::process::exit(EXIT_OPTIMIZER_ERROR);
+            }
+        };
+    }
+
+    if emit == "ast" || verbose {
+        println!("===== AST =====");
+        result_node.print(0);
+        println!();
+    }
+
+    if emit == "ast" {
+        return;
+    }
+
+    if verbose {
+        println!("===== Code Generation =====");
+    }
+
+    // The generator reports errors by panicking with a GeneratorError, those
+    // are printed below so only other panics go through the default hook
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !info.payload().is::<GeneratorError>() {
+            default_hook(info);
+        }
+    }));
+    let generated = panic::catch_unwind(AssertUnwindSafe(|| match (emit, target) {
+        // The IR is printed like the other stages instead of being written to the output file
+        ("ir", _) => generate(
+            IrCodeGenerator::new("-", verbose, optimization_level),
+            &result_node,
+            false,
+        ),
+        (_, "aarch64") => generate(
+            Arm64CodeGenerator::new(output_file, verbose, optimization_level),
+            &result_node,
+            with_runtime,
+        ),
+        _ => {
+            let mut generator = X86CodeGenerator::new(output_file, verbose, optimization_level);
+            generator.set_syntax(syntax);
+            generator.set_pie(pie);
+            generate(generator, &result_node, with_runtime);
+        }
+    }));
+    let _ = panic::take_hook();
+
+    if let Err(payload) = generated {
+        match payload.downcast::<GeneratorError>() {
+            Ok(error) => {
+                match *error {
+                    GeneratorError::Located(error) => eprintln!(
+                        "Generator error in {} at {}",
+                        source_name,
+                        error.format_with_source(&input)
+                    ),
+                    GeneratorError::Unlocated(message) => {
+                        eprintln!("Generator error in {}: {}", source_name, message)
+                    }
+                }
+                std::process::exit(EXIT_GENERATOR_ERROR);
+            }
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    if emit == "ir" {
+        return;
+    }
+
+    if build {
+        // There is no file name to use when the source comes from stdin
+        let executable = if input_file == "-" {
+            "a.out"
+        } else {
+            Path::new(input_file).file_stem().unwrap().to_str().unwrap()
+        };
+        let runtime_file = if with_runtime {
+            None
+        } else {
+            matches.value_of("runtime")
+        };
+
+        if let Err(error) = build_executable(output_file, runtime_file, executable, pie) {
+            eprintln!("Build error: {}", error);
+            std::process::exit(1);
+        }
+    }
 }
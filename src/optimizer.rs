@@ -0,0 +1,354 @@
+use crate::ast::*;
+use crate::error::CompileError;
+use crate::types::*;
+
+/// Returns the value of a condition when it is known at compile time
+fn get_constant_condition(condition: &AstNode) -> Option<bool> {
+    match condition {
+        AstNode::NumericLiteral(PrimitiveType::Bool, value) => {
+            Some(value.as_u64(&PrimitiveType::Bool) != 0)
+        }
+        _ => None,
+    }
+}
+
+/// Removes the branches and loops which can never be executed
+///
+/// An if statement with a constant condition is replaced by the branch that
+/// is taken and loops with a constant false condition are dropped. Returns
+/// `None` when the whole node can be removed.
+pub fn eliminate_dead_code(node: AstNode) -> Option<AstNode> {
+    match node {
+        AstNode::Block(children, result) => Some(AstNode::Block(
+            children
+                .into_iter()
+                .filter_map(eliminate_dead_code)
+                .collect(),
+            result,
+        )),
+        AstNode::Function(symbol, code) => Some(AstNode::Function(
+            symbol,
+            Box::new(eliminate_dead_code(*code)?),
+        )),
+        AstNode::If(condition, code, else_code) => match get_constant_condition(&condition) {
+            Some(true) => eliminate_dead_code(*code),
+            Some(false) => else_code.and_then(|else_code| eliminate_dead_code(*else_code)),
+            None => Some(AstNode::If(
+                condition,
+                Box::new(eliminate_dead_code(*code)?),
+                else_code
+                    .and_then(|else_code| eliminate_dead_code(*else_code))
+                    .map(Box::new),
+            )),
+        },
+        AstNode::While(condition, code) => match get_constant_condition(&condition) {
+            Some(false) => None,
+            _ => Some(AstNode::While(
+                condition,
+                Box::new(eliminate_dead_code(*code)?),
+            )),
+        },
+        // The initializer of a for loop still runs when the body never does
+        AstNode::For(init, condition, update, code) => match get_constant_condition(&condition) {
+            Some(false) => Some(*init),
+            _ => Some(AstNode::For(
+                init,
+                condition,
+                update,
+                Box::new(eliminate_dead_code(*code)?),
+            )),
+        },
+        node => Some(node),
+    }
+}
+
+/// Returns the value of an integer or boolean literal together with its type
+fn get_literal(node: &AstNode) -> Option<(PrimitiveType, u64)> {
+    match node {
+        AstNode::NumericLiteral(primitive_type, value)
+            if primitive_type.is_integer() || *primitive_type == PrimitiveType::Bool =>
+        {
+            Some((primitive_type.clone(), value.as_u64(primitive_type)))
+        }
+        _ => None,
+    }
+}
+
+fn make_literal(primitive_type: PrimitiveType, value: u64) -> AstNode {
+    AstNode::NumericLiteral(primitive_type, PrimitiveValue { uint64: value })
+}
+
+/// Calculates the result of a binary operation on two literals
+///
+/// Signed values are sign extended to 64 bits, so the low bits of the result
+/// are the same as the ones the operation would have produced at runtime.
+/// Returns `None` for operations which would fault or are undefined.
+fn fold_binary_operation(
+    operation_type: BinaryOperationType,
+    left: u64,
+    right: u64,
+    signed: bool,
+    size: i32,
+) -> Option<u64> {
+    let result = match operation_type {
+        BinaryOperationType::Add => left.wrapping_add(right),
+        BinaryOperationType::Subtract => left.wrapping_sub(right),
+        BinaryOperationType::Multiply => left.wrapping_mul(right),
+        BinaryOperationType::Divide | BinaryOperationType::Modulo if right == 0 => return None,
+        BinaryOperationType::Divide if signed => (left as i64).wrapping_div(right as i64) as u64,
+        BinaryOperationType::Divide => left / right,
+        BinaryOperationType::Modulo if signed => (left as i64).wrapping_rem(right as i64) as u64,
+        BinaryOperationType::Modulo => left % right,
+        BinaryOperationType::BitAnd => left & right,
+        BinaryOperationType::BitOr => left | right,
+        BinaryOperationType::BitXor => left ^ right,
+        BinaryOperationType::ShiftLeft | BinaryOperationType::ShiftRight
+            if right >= size as u64 =>
+        {
+            return None
+        }
+        BinaryOperationType::ShiftLeft => left << right,
+        BinaryOperationType::ShiftRight if signed => ((left as i64) >> right) as u64,
+        BinaryOperationType::ShiftRight => left >> right,
+        BinaryOperationType::Equals => (left == right) as u64,
+        BinaryOperationType::NotEquals => (left != right) as u64,
+        BinaryOperationType::LessThan if signed => ((left as i64) < (right as i64)) as u64,
+        BinaryOperationType::LessThan => (left < right) as u64,
+        BinaryOperationType::LessThanOrEqual if signed => ((left as i64) <= (right as i64)) as u64,
+        BinaryOperationType::LessThanOrEqual => (left <= right) as u64,
+        BinaryOperationType::GreaterThan if signed => ((left as i64) > (right as i64)) as u64,
+        BinaryOperationType::GreaterThan => (left > right) as u64,
+        BinaryOperationType::GreaterThanOrEqual if signed => {
+            ((left as i64) >= (right as i64)) as u64
+        }
+        BinaryOperationType::GreaterThanOrEqual => (left >= right) as u64,
+        BinaryOperationType::LogicalAnd => (left != 0 && right != 0) as u64,
+        BinaryOperationType::LogicalOr => (left != 0 || right != 0) as u64,
+    };
+
+    Some(result)
+}
+
+/// Checks whether the exact result of an arithmetic operation can't be
+/// represented in the type of its operands
+fn overflows(
+    operation_type: BinaryOperationType,
+    left: u64,
+    right: u64,
+    primitive_type: &PrimitiveType,
+) -> bool {
+    let size = primitive_type.get_size() as u32;
+    let (left, right, min, max) = if primitive_type.is_signed() {
+        (
+            left as i64 as i128,
+            right as i64 as i128,
+            -(1i128 << (size - 1)),
+            (1i128 << (size - 1)) - 1,
+        )
+    } else {
+        (left as i128, right as i128, 0, (1i128 << size) - 1)
+    };
+
+    let result = match operation_type {
+        BinaryOperationType::Add => left.checked_add(right),
+        BinaryOperationType::Subtract => left.checked_sub(right),
+        BinaryOperationType::Multiply => left.checked_mul(right),
+        // Only the smallest signed value divided by -1 overflows
+        BinaryOperationType::Divide if right != 0 => left.checked_div(right),
+        _ => return false,
+    };
+
+    !result.is_some_and(|result| (min..=max).contains(&result))
+}
+
+/// Replaces the operations on integer and boolean literals by their result
+///
+/// Operations are computed in the type of their operands. When the result
+/// doesn't fit in that type it wraps around like it would at runtime and a
+/// warning is reported, or an error when overflows are denied.
+pub struct ConstantFolder {
+    deny_overflow: bool,
+    warnings: Vec<CompileError>,
+    errors: Vec<CompileError>,
+}
+
+impl ConstantFolder {
+    pub fn new(deny_overflow: bool) -> Self {
+        ConstantFolder {
+            deny_overflow,
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn warnings(&self) -> &[CompileError] {
+        &self.warnings
+    }
+
+    pub fn fold(&mut self, node: AstNode) -> Result<AstNode, Vec<CompileError>> {
+        let node = self.fold_constants(node);
+
+        if self.errors.is_empty() {
+            Ok(node)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn report_overflow(&mut self, span: Span, primitive_type: &PrimitiveType, value: u64) {
+        let message = format!(
+            "Result of the constant expression doesn't fit in {:?}",
+            primitive_type
+        );
+
+        if self.deny_overflow {
+            self.errors
+                .push(CompileError::new(span.line, span.col, &message));
+        } else {
+            let value = PrimitiveValue { uint64: value }.as_u64(primitive_type);
+            let wrapped = if primitive_type.is_signed() {
+                (value as i64).to_string()
+            } else {
+                value.to_string()
+            };
+            self.warnings.push(CompileError::new(
+                span.line,
+                span.col,
+                &format!("{}, it wraps around to {}", message, wrapped),
+            ));
+        }
+    }
+
+    fn fold_boxed(&mut self, node: AstNode) -> Box<AstNode> {
+        Box::new(self.fold_constants(node))
+    }
+
+    fn fold_constants(&mut self, node: AstNode) -> AstNode {
+        match node {
+            AstNode::BinaryOperation(operation_type, left, right, span) => {
+                let left = self.fold_boxed(*left);
+                let right = self.fold_boxed(*right);
+
+                if let (Some((left_type, left_value)), Some((_, right_value))) =
+                    (get_literal(&left), get_literal(&right))
+                {
+                    let node = AstNode::BinaryOperation(operation_type, left, right, span);
+                    let primitive_type = node.get_primitive_type();
+
+                    if let Some(value) = fold_binary_operation(
+                        operation_type,
+                        left_value,
+                        right_value,
+                        left_type.is_signed(),
+                        left_type.get_size(),
+                    ) {
+                        if overflows(operation_type, left_value, right_value, &left_type) {
+                            self.report_overflow(span, &primitive_type, value);
+                        }
+
+                        return make_literal(primitive_type, value);
+                    }
+
+                    return node;
+                }
+
+                AstNode::BinaryOperation(operation_type, left, right, span)
+            }
+            AstNode::UnaryOperation(operation_type, node) => {
+                let node = AstNode::UnaryOperation(operation_type, self.fold_boxed(*node));
+
+                if let AstNode::UnaryOperation(_, operand) = &node {
+                    if let Some((_, value)) = get_literal(operand) {
+                        let result = match operation_type {
+                            UnaryOperationType::Negate => value.wrapping_neg(),
+                            UnaryOperationType::Not => (value == 0) as u64,
+                            UnaryOperationType::BitNot => !value,
+                        };
+
+                        return make_literal(node.get_primitive_type(), result);
+                    }
+                }
+
+                node
+            }
+            AstNode::Widen(primitive_type, node) => {
+                let node = self.fold_boxed(*node);
+
+                match get_literal(&node) {
+                    Some((_, value)) if primitive_type.is_integer() => {
+                        make_literal(primitive_type, value)
+                    }
+                    _ => AstNode::Widen(primitive_type, node),
+                }
+            }
+            AstNode::Cast(primitive_type, node) => {
+                AstNode::Cast(primitive_type, self.fold_boxed(*node))
+            }
+            AstNode::Ternary(condition, then_value, else_value) => AstNode::Ternary(
+                self.fold_boxed(*condition),
+                self.fold_boxed(*then_value),
+                self.fold_boxed(*else_value),
+            ),
+            AstNode::VariableDeclaration(symbol, initializer, span) => {
+                AstNode::VariableDeclaration(
+                    symbol,
+                    initializer.map(|initializer| self.fold_boxed(*initializer)),
+                    span,
+                )
+            }
+            AstNode::GlobalDeclaration(symbol, initializer) => AstNode::GlobalDeclaration(
+                symbol,
+                initializer.map(|initializer| self.fold_boxed(*initializer)),
+            ),
+            AstNode::Assignment(symbol, node, span) => {
+                AstNode::Assignment(symbol, self.fold_boxed(*node), span)
+            }
+            AstNode::FunctionCall(symbol, params) => AstNode::FunctionCall(
+                symbol,
+                params
+                    .into_iter()
+                    .map(|node| self.fold_constants(node))
+                    .collect(),
+            ),
+            AstNode::Printf(params) => AstNode::Printf(
+                params
+                    .into_iter()
+                    .map(|node| self.fold_constants(node))
+                    .collect(),
+            ),
+            AstNode::Dereference(node) => AstNode::Dereference(self.fold_boxed(*node)),
+            AstNode::DereferenceAssignment(pointer, value) => {
+                AstNode::DereferenceAssignment(self.fold_boxed(*pointer), self.fold_boxed(*value))
+            }
+            AstNode::Index(symbol, index) => AstNode::Index(symbol, self.fold_boxed(*index)),
+            AstNode::IndexAssignment(symbol, index, value) => {
+                AstNode::IndexAssignment(symbol, self.fold_boxed(*index), self.fold_boxed(*value))
+            }
+            AstNode::Function(symbol, code) => AstNode::Function(symbol, self.fold_boxed(*code)),
+            AstNode::If(condition, code, else_code) => AstNode::If(
+                self.fold_boxed(*condition),
+                self.fold_boxed(*code),
+                else_code.map(|else_code| self.fold_boxed(*else_code)),
+            ),
+            AstNode::While(condition, code) => {
+                AstNode::While(self.fold_boxed(*condition), self.fold_boxed(*code))
+            }
+            AstNode::For(init, condition, update, code) => AstNode::For(
+                self.fold_boxed(*init),
+                self.fold_boxed(*condition),
+                self.fold_boxed(*update),
+                self.fold_boxed(*code),
+            ),
+            AstNode::Assert(condition) => AstNode::Assert(self.fold_boxed(*condition)),
+            AstNode::Return(value) => AstNode::Return(value.map(|value| self.fold_boxed(*value))),
+            AstNode::Block(children, result) => AstNode::Block(
+                children
+                    .into_iter()
+                    .map(|node| self.fold_constants(node))
+                    .collect(),
+                result.map(|result| self.fold_boxed(*result)),
+            ),
+            node => node,
+        }
+    }
+}
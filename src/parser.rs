@@ -1,48 +1,241 @@
 use crate::ast::*;
+use crate::error::*;
 use crate::lexer::*;
 use crate::scope::*;
 use crate::types::*;
 
 use std::cmp::Ordering;
+use std::collections::HashSet;
 
 #[derive(PartialEq, PartialOrd, Clone, Copy)]
 pub enum OperatorPrecedence {
     MulDiv = 200,
     AddSubtract = 150,
+    Shift = 125,
     LessGreaterThan = 100,
     EqualsNotEquals = 50,
+    BitAnd = 45,
+    BitXor = 40,
+    BitOr = 35,
+    LogicalAnd = 30,
+    LogicalOr = 20,
     Zero = 0,
 }
 
+type ParseResult<T> = Result<T, CompileError>;
+
+/// The format string of printf takes the first of the six argument registers
+const PRINTF_ARGUMENT_LIMIT: usize = 5;
+
+/// The symbol types which can be used as a value
+const VARIABLE_SYMBOL_TYPES: &[SymbolType] = &[
+    SymbolType::Variable,
+    SymbolType::Global,
+    SymbolType::FunctionParameter,
+];
+
 pub struct Parser {
     tokens: Vec<Token>,
     index: usize,
     scope: Vec<Scope>,
+    function_return_type: Option<PrimitiveType>,
+    loop_depth: usize,
+    errors: Vec<CompileError>,
+    warnings: Vec<CompileError>,
+    /// The token indices of the top level functions which were declared up front
+    declared_functions: HashSet<usize>,
 }
 
-fn token_type_to_operator(token_type: TokenType) -> BinaryOperationType {
+fn token_type_to_operator(token_type: TokenType) -> Option<BinaryOperationType> {
     match token_type {
-        TokenType::Plus => BinaryOperationType::Add,
-        TokenType::Minus => BinaryOperationType::Subtract,
-        TokenType::Star => BinaryOperationType::Multiply,
-        TokenType::Slash => BinaryOperationType::Divide,
-        TokenType::DoubleEqualSign => BinaryOperationType::Equals,
-        TokenType::NotEqualSign => BinaryOperationType::NotEquals,
-        TokenType::LessThan => BinaryOperationType::LessThan,
-        TokenType::LessThanOrEqual => BinaryOperationType::LessThanOrEqual,
-        TokenType::GreaterThan => BinaryOperationType::GreaterThan,
-        TokenType::GreaterThanOrEqual => BinaryOperationType::GreaterThanOrEqual,
-        _ => panic!(
-            "Trying to convert a non operator token type to a binary operator type, {:?}",
-            token_type
+        TokenType::Plus => Some(BinaryOperationType::Add),
+        TokenType::Minus => Some(BinaryOperationType::Subtract),
+        TokenType::Star => Some(BinaryOperationType::Multiply),
+        TokenType::Slash => Some(BinaryOperationType::Divide),
+        TokenType::Percent => Some(BinaryOperationType::Modulo),
+        TokenType::DoubleEqualSign => Some(BinaryOperationType::Equals),
+        TokenType::NotEqualSign => Some(BinaryOperationType::NotEquals),
+        TokenType::LessThan => Some(BinaryOperationType::LessThan),
+        TokenType::LessThanOrEqual => Some(BinaryOperationType::LessThanOrEqual),
+        TokenType::GreaterThan => Some(BinaryOperationType::GreaterThan),
+        TokenType::GreaterThanOrEqual => Some(BinaryOperationType::GreaterThanOrEqual),
+        TokenType::DoubleAmpersand => Some(BinaryOperationType::LogicalAnd),
+        TokenType::DoublePipe => Some(BinaryOperationType::LogicalOr),
+        TokenType::Ampersand => Some(BinaryOperationType::BitAnd),
+        TokenType::Pipe => Some(BinaryOperationType::BitOr),
+        TokenType::Caret => Some(BinaryOperationType::BitXor),
+        TokenType::ShiftLeft => Some(BinaryOperationType::ShiftLeft),
+        TokenType::ShiftRight => Some(BinaryOperationType::ShiftRight),
+        _ => None,
+    }
+}
+
+/// Computes the Levenshtein distance between two names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Unsigned literals can be used in a signed expression, they get the
+/// smallest signed type which is able to hold their value.
+fn make_literal_signed(node: AstNode) -> AstNode {
+    if let AstNode::NumericLiteral(primitive_type, value) = &node {
+        if primitive_type.is_unsigned() {
+            let value = value.as_u64(primitive_type);
+            let mut primitive_type = PrimitiveType::Int8;
+
+            if value > 2u64.pow(31) - 1 {
+                primitive_type = PrimitiveType::Int64;
+            } else if value > 2u64.pow(15) - 1 {
+                primitive_type = PrimitiveType::Int32;
+            } else if value > 2u64.pow(7) - 1 {
+                primitive_type = PrimitiveType::Int16;
+            }
+
+            return AstNode::NumericLiteral(primitive_type, PrimitiveValue { uint64: value });
+        }
+    }
+
+    node
+}
+
+/// Float literals are 64 bit by default, they are narrowed when they are used
+/// together with a 32 bit float.
+fn make_literal_float32(node: AstNode) -> AstNode {
+    if let AstNode::NumericLiteral(PrimitiveType::Float64, value) = &node {
+        let value = value.as_f64(&PrimitiveType::Float64) as f32;
+
+        return AstNode::NumericLiteral(
+            PrimitiveType::Float32,
+            PrimitiveValue {
+                uint64: value.to_bits() as u64,
+            },
+        );
+    }
+
+    node
+}
+
+/// Returns the value of an integer literal, which might be negated
+fn get_integer_literal(node: &AstNode) -> Option<i128> {
+    match node {
+        AstNode::NumericLiteral(primitive_type, value) if primitive_type.is_signed() => {
+            Some(value.as_u64(primitive_type) as i64 as i128)
+        }
+        AstNode::NumericLiteral(primitive_type, value) if primitive_type.is_unsigned() => {
+            Some(value.as_u64(primitive_type) as i128)
+        }
+        AstNode::UnaryOperation(UnaryOperationType::Negate, node) => {
+            get_integer_literal(node).map(|value| -value)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the smallest and largest value an integer type can hold
+fn get_integer_range(primitive_type: &PrimitiveType) -> (i128, i128) {
+    let size = primitive_type.get_size() as u32;
+
+    if primitive_type.is_signed() {
+        (-(1i128 << (size - 1)), (1i128 << (size - 1)) - 1)
+    } else {
+        (0, (1i128 << size) - 1)
+    }
+}
+
+/// Checks whether an expression only does arithmetic on integer literals
+/// which all fit in the given type
+fn is_constant_arithmetic(node: &AstNode, primitive_type: &PrimitiveType) -> bool {
+    match node {
+        AstNode::BinaryOperation(operation_type, left, right, _) => {
+            !is_comparison(*operation_type)
+                && !matches!(
+                    operation_type,
+                    BinaryOperationType::LogicalAnd | BinaryOperationType::LogicalOr
+                )
+                && is_constant_arithmetic(left, primitive_type)
+                && is_constant_arithmetic(right, primitive_type)
+        }
+        AstNode::Widen(_, node) => is_constant_arithmetic(node, primitive_type),
+        node => get_integer_literal(node).is_some_and(|value| {
+            let (min, max) = get_integer_range(primitive_type);
+            value >= min && value <= max
+        }),
+    }
+}
+
+/// Gives the literals of a constant arithmetic expression the type it is
+/// assigned to, so `200 + 100` is computed as a u16 when it's assigned to one
+/// instead of overflowing the u8 its literals would get on their own.
+fn retype_constant_arithmetic(node: AstNode, primitive_type: &PrimitiveType) -> AstNode {
+    match node {
+        AstNode::BinaryOperation(operation_type, left, right, span) => AstNode::BinaryOperation(
+            operation_type,
+            Box::new(retype_constant_arithmetic(*left, primitive_type)),
+            Box::new(retype_constant_arithmetic(*right, primitive_type)),
+            span,
+        ),
+        AstNode::Widen(_, node) => retype_constant_arithmetic(*node, primitive_type),
+        node => AstNode::NumericLiteral(
+            primitive_type.clone(),
+            PrimitiveValue {
+                int64: get_integer_literal(&node).unwrap() as i64,
+            },
         ),
     }
 }
 
+/// Checks whether a token can be the start of a unary expression
+fn is_operand_start(token_type: TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::IntLiteral
+            | TokenType::FloatLiteral
+            | TokenType::BoolLiteral
+            | TokenType::CharLiteral
+            | TokenType::StringLiteral
+            | TokenType::LeftParen
+            | TokenType::LeftBrace
+            | TokenType::Identifier
+            | TokenType::Minus
+            | TokenType::ExclamationMark
+            | TokenType::Tilde
+            | TokenType::Ampersand
+            | TokenType::Star
+    )
+}
+
+fn is_comparison(operation_type: BinaryOperationType) -> bool {
+    matches!(
+        operation_type,
+        BinaryOperationType::Equals
+            | BinaryOperationType::NotEquals
+            | BinaryOperationType::LessThan
+            | BinaryOperationType::LessThanOrEqual
+            | BinaryOperationType::GreaterThan
+            | BinaryOperationType::GreaterThanOrEqual
+    )
+}
+
 fn get_operator_precedence(operation_type: BinaryOperationType) -> OperatorPrecedence {
     match operation_type {
         BinaryOperationType::Add | BinaryOperationType::Subtract => OperatorPrecedence::AddSubtract,
-        BinaryOperationType::Multiply | BinaryOperationType::Divide => OperatorPrecedence::MulDiv,
+        BinaryOperationType::Multiply
+        | BinaryOperationType::Divide
+        | BinaryOperationType::Modulo => OperatorPrecedence::MulDiv,
         BinaryOperationType::Equals | BinaryOperationType::NotEquals => {
             OperatorPrecedence::EqualsNotEquals
         }
@@ -50,6 +243,14 @@ fn get_operator_precedence(operation_type: BinaryOperationType) -> OperatorPrece
         | BinaryOperationType::LessThanOrEqual
         | BinaryOperationType::GreaterThan
         | BinaryOperationType::GreaterThanOrEqual => OperatorPrecedence::LessGreaterThan,
+        BinaryOperationType::LogicalAnd => OperatorPrecedence::LogicalAnd,
+        BinaryOperationType::LogicalOr => OperatorPrecedence::LogicalOr,
+        BinaryOperationType::BitAnd => OperatorPrecedence::BitAnd,
+        BinaryOperationType::BitOr => OperatorPrecedence::BitOr,
+        BinaryOperationType::BitXor => OperatorPrecedence::BitXor,
+        BinaryOperationType::ShiftLeft | BinaryOperationType::ShiftRight => {
+            OperatorPrecedence::Shift
+        }
     }
 }
 
@@ -59,82 +260,131 @@ impl Parser {
             tokens,
             index: 0,
             scope: vec![Scope::new()],
+            function_return_type: None,
+            loop_depth: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            declared_functions: HashSet::new(),
         };
         parser.setup_libc();
         parser
     }
 
+    /// Returns the scopes which are still open, after parsing this is only the
+    /// global scope with the functions, globals and libc symbols
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scope
+    }
+
+    /// Returns the problems found while parsing which don't stop compilation
+    pub fn warnings(&self) -> &[CompileError] {
+        &self.warnings
+    }
+
     fn setup_libc(&mut self) {
-        self.add_to_scope(
-            &"printbool".to_string(),
-            PrimitiveType::Void,
-            vec![PrimitiveType::Bool],
-            SymbolType::Function,
-        );
-        self.add_to_scope(
-            &"print8".to_string(),
-            PrimitiveType::Void,
-            vec![PrimitiveType::UInt8],
-            SymbolType::Function,
-        );
-        self.add_to_scope(
-            &"print16".to_string(),
-            PrimitiveType::Void,
-            vec![PrimitiveType::UInt16],
-            SymbolType::Function,
-        );
-        self.add_to_scope(
-            &"print32".to_string(),
-            PrimitiveType::Void,
-            vec![PrimitiveType::UInt32],
-            SymbolType::Function,
-        );
-        self.add_to_scope(
-            &"print64".to_string(),
-            PrimitiveType::Void,
-            vec![PrimitiveType::UInt64],
-            SymbolType::Function,
-        );
-        self.add_to_scope(
-            &"printsum".to_string(),
-            PrimitiveType::Void,
-            vec![PrimitiveType::UInt32, PrimitiveType::UInt32],
-            SymbolType::Function,
-        );
+        let functions = [
+            ("printbool", vec![PrimitiveType::Bool]),
+            ("printchar", vec![PrimitiveType::Char]),
+            ("print8", vec![PrimitiveType::UInt8]),
+            ("print16", vec![PrimitiveType::UInt16]),
+            ("print32", vec![PrimitiveType::UInt32]),
+            ("print64", vec![PrimitiveType::UInt64]),
+            ("printi8", vec![PrimitiveType::Int8]),
+            ("printi16", vec![PrimitiveType::Int16]),
+            ("printi32", vec![PrimitiveType::Int32]),
+            ("printi64", vec![PrimitiveType::Int64]),
+            (
+                "printsum",
+                vec![PrimitiveType::UInt32, PrimitiveType::UInt32],
+            ),
+        ];
+
+        for (name, parameter_types) in functions {
+            self.scope[0].add(
+                name,
+                PrimitiveType::Void,
+                parameter_types,
+                SymbolType::Function,
+            );
+        }
     }
 
-    fn error(&self, message: &str) {
-        eprintln!(
-            "Parser error at line {}:{}\n{}",
-            self.tokens[self.index].line, self.tokens[self.index].col, message
-        );
-        panic!();
+    fn error(&self, message: &str) -> CompileError {
+        self.error_at(self.index, message)
+    }
+
+    fn span_at(&self, token_index: usize) -> Span {
+        let token = &self.tokens[token_index.min(self.tokens.len() - 1)];
+
+        Span {
+            line: token.line,
+            col: token.col,
+        }
+    }
+
+    fn error_at(&self, token_index: usize, message: &str) -> CompileError {
+        // Errors at the end of the stream are reported at the last token
+        let token = &self.tokens[token_index.min(self.tokens.len() - 1)];
+
+        CompileError::new(token.line, token.col, message)
+    }
+
+    /// Records an error and skips ahead to a point where parsing can resume
+    ///
+    /// Tokens are skipped up to and including the next semicolon or block,
+    /// or up to the next closing brace so the enclosing block can still be closed.
+    fn recover(&mut self, error: CompileError) {
+        self.errors.push(error);
+
+        let mut depth = 0;
+
+        while !self.eof() {
+            let token_type = self.tokens[self.index].token_type;
+
+            if token_type == TokenType::RightBrace && depth == 0 {
+                break;
+            }
+
+            self.index += 1;
+
+            match token_type {
+                TokenType::SemiColon if depth == 0 => break,
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
     }
 
-    fn peek(&self, index: usize) -> &Token {
+    fn peek(&self, index: usize) -> ParseResult<&Token> {
         if self.index + index >= self.tokens.len() {
-            self.error("Reached end of tokenstream while peeking!");
+            return Err(self.error("Reached end of tokenstream while peeking!"));
         }
-        &self.tokens[self.index + index]
+        Ok(&self.tokens[self.index + index])
     }
 
-    fn consume(&mut self) -> &Token {
+    fn consume(&mut self) -> ParseResult<&Token> {
         if self.eof() {
-            self.error("Reached end of tokenstream while consuming!");
+            return Err(self.error("Reached end of tokenstream while consuming!"));
         }
         let result = &self.tokens[self.index];
         self.index += 1;
 
-        result
+        Ok(result)
     }
 
-    fn assert_consume(&mut self, token_type: TokenType) -> &Token {
-        let token = self.peek(0);
+    fn assert_consume(&mut self, token_type: TokenType) -> ParseResult<&Token> {
+        let token = self.peek(0)?;
         if token.token_type != token_type {
-            self.error(&format!(
+            return Err(self.error(&format!(
                 "Assert consume failed: {:?} != {:?}",
                 token.token_type, token_type
-            ));
+            )));
         }
         self.consume()
     }
@@ -146,66 +396,228 @@ impl Parser {
     fn find_scope_var(&self, name: &str) -> Option<&Symbol> {
         for scope in self.scope.iter().rev() {
             if let Some(var) = scope.get(name) {
-                return Some(&var);
+                return Some(var);
             }
         }
 
         None
     }
 
+    /// Marks the variable with the given name as read in the scope that declares it
+    fn mark_read(&mut self, name: &str) {
+        for scope in self.scope.iter_mut().rev() {
+            if scope.get(name).is_some() {
+                scope.unread.remove(name);
+                return;
+            }
+        }
+    }
+
+    /// Pops the innermost scope and warns about the variables which were never read
+    fn pop_scope(&mut self) {
+        let scope = self.scope.pop().unwrap();
+
+        let mut unread: Vec<(String, usize)> = scope.unread.into_iter().collect();
+        unread.sort_by_key(|(_, token_index)| *token_index);
+
+        for (name, token_index) in unread {
+            let warning = self.error_at(token_index, &format!("unused variable {}", name));
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Looks up the symbol named by the identifier token at the given index
+    fn find_symbol(&self, token_index: usize, symbol_types: &[SymbolType]) -> ParseResult<Symbol> {
+        let name = &self.tokens[token_index].value;
+
+        for scope in self.scope.iter().rev() {
+            if let Some(symbol) = scope.get_of_type(name, symbol_types) {
+                return Ok(symbol.clone());
+            }
+        }
+
+        let message = match self.find_scope_var(name) {
+            Some(symbol) if symbol.symbol_type == SymbolType::Function => {
+                format!("{} is a function, not a variable", name)
+            }
+            Some(_) => format!("{} is not a function", name),
+            None => match self.find_similar_name(name, symbol_types) {
+                Some(similar) => format!("Unknown identifier: {}, did you mean {}?", name, similar),
+                None => format!("Unknown identifier: {}", name),
+            },
+        };
+
+        Err(self.error_at(token_index, &message))
+    }
+
+    /// Finds the visible symbol name closest to a misspelled one
+    fn find_similar_name(&self, name: &str, symbol_types: &[SymbolType]) -> Option<&str> {
+        // Only suggest names which differ in about a third of their characters
+        let max_distance = (name.chars().count() / 3).max(1);
+
+        self.scope
+            .iter()
+            .flat_map(|scope| scope.symbols.values())
+            .filter(|symbol| symbol_types.contains(&symbol.symbol_type))
+            .map(|symbol| (edit_distance(name, &symbol.name), symbol.name.as_str()))
+            .filter(|(distance, _)| *distance <= max_distance)
+            // Ties are broken by name to keep the suggestion deterministic
+            .min_by_key(|&(distance, similar)| (distance, similar))
+            .map(|(_, similar)| similar)
+    }
+
+    /// Checks that the name is still free in the innermost scope, the error
+    /// points at the name token at the given index
+    fn check_redeclaration(&self, name_index: usize, name: &str) -> ParseResult<()> {
+        let scope_count = self.scope.len();
+        if self.scope[scope_count - 1].get(name).is_some() {
+            return Err(self.error_at(
+                name_index,
+                &format!("Redeclaration of {} in the same scope", name),
+            ));
+        }
+
+        Ok(())
+    }
+
     fn add_to_scope(
         &mut self,
+        name_index: usize,
         name: &str,
         primitive_type: PrimitiveType,
         parameter_types: Vec<PrimitiveType>,
         symbol_type: SymbolType,
-    ) -> Symbol {
+    ) -> ParseResult<Symbol> {
+        self.check_redeclaration(name_index, name)?;
+
         let scope_count = self.scope.len();
-        self.scope[scope_count - 1].add(name, primitive_type, parameter_types, symbol_type)
+        Ok(self.scope[scope_count - 1].add(name, primitive_type, parameter_types, symbol_type))
     }
 
     fn add_to_scope_with_offset(
         &mut self,
+        name_index: usize,
         name: &str,
         primitive_type: PrimitiveType,
         parameter_types: Vec<PrimitiveType>,
         symbol_type: SymbolType,
         offset: i32,
-    ) -> Symbol {
+    ) -> ParseResult<Symbol> {
+        self.check_redeclaration(name_index, name)?;
+
         let scope_count = self.scope.len();
-        self.scope[scope_count - 1].add_with_offset(
+        Ok(self.scope[scope_count - 1].add_with_offset(
             name,
             primitive_type,
             parameter_types,
             symbol_type,
             offset,
-        )
+        ))
     }
 
-    fn parse_unary_expression(&mut self) -> AstNode {
-        let current_token = self.peek(0);
-        if current_token.token_type != TokenType::IntLiteral
-            && current_token.token_type != TokenType::LeftParen
-            && current_token.token_type != TokenType::Identifier
-        {
-            self.error(
-                "parse_unary_expression expects IntLiteral, LeftParen or Identifier token type",
-            );
+    fn parse_unary_expression(&mut self) -> ParseResult<AstNode> {
+        let current_token = self.peek(0)?;
+        if !is_operand_start(current_token.token_type) {
+            return Err(self.error(
+                "parse_unary_expression expects a literal, LeftParen, Identifier or a prefix operator token type",
+            ));
         }
 
         match current_token.token_type {
+            TokenType::Minus => {
+                self.assert_consume(TokenType::Minus)?;
+                let mut expression = self.parse_unary_expression()?;
+
+                if !expression.get_primitive_type().is_integer() {
+                    return Err(self.error("Negation expects an integer expression"));
+                }
+
+                // Make sure the negated literal still fits in the signed counterpart of its type
+                if let AstNode::NumericLiteral(literal_type, value) = &expression {
+                    let value = value.as_u64(literal_type);
+                    let mut primitive_type = PrimitiveType::UInt8;
+
+                    if value > 2u64.pow(31) {
+                        primitive_type = PrimitiveType::UInt64;
+                    } else if value > 2u64.pow(15) {
+                        primitive_type = PrimitiveType::UInt32;
+                    } else if value > 2u64.pow(7) {
+                        primitive_type = PrimitiveType::UInt16;
+                    }
+
+                    expression =
+                        AstNode::NumericLiteral(primitive_type, PrimitiveValue { uint64: value });
+                }
+
+                Ok(AstNode::UnaryOperation(
+                    UnaryOperationType::Negate,
+                    Box::new(expression),
+                ))
+            }
+            TokenType::ExclamationMark => {
+                self.assert_consume(TokenType::ExclamationMark)?;
+                let expression = self.parse_unary_expression()?;
+
+                if expression.get_primitive_type() != PrimitiveType::Bool {
+                    return Err(self.error("Logical not expects a boolean expression"));
+                }
+
+                Ok(AstNode::UnaryOperation(
+                    UnaryOperationType::Not,
+                    Box::new(expression),
+                ))
+            }
+            TokenType::Tilde => {
+                self.assert_consume(TokenType::Tilde)?;
+                let expression = self.parse_unary_expression()?;
+
+                if !expression.get_primitive_type().is_integer() {
+                    return Err(self.error("Bitwise not expects an integer expression"));
+                }
+
+                Ok(AstNode::UnaryOperation(
+                    UnaryOperationType::BitNot,
+                    Box::new(expression),
+                ))
+            }
+            TokenType::Ampersand => {
+                self.assert_consume(TokenType::Ampersand)?;
+
+                let identifier_index = self.index;
+                self.assert_consume(TokenType::Identifier)?;
+                let scope_var = self.find_symbol(identifier_index, VARIABLE_SYMBOL_TYPES)?;
+                self.mark_read(&scope_var.name);
+
+                Ok(AstNode::AddressOf(scope_var))
+            }
+            TokenType::Star => {
+                self.assert_consume(TokenType::Star)?;
+                let expression = self.parse_unary_expression()?;
+
+                if !expression.get_primitive_type().is_pointer() {
+                    return Err(self.error("Dereference expects a pointer expression"));
+                }
+
+                Ok(AstNode::Dereference(Box::new(expression)))
+            }
             TokenType::LeftParen => {
-                self.assert_consume(TokenType::LeftParen);
-                let expression = self.parse_expression(OperatorPrecedence::Zero);
-                self.assert_consume(TokenType::RightParen);
-                expression
+                self.assert_consume(TokenType::LeftParen)?;
+                let expression = self.parse_expression(OperatorPrecedence::Zero)?;
+                self.assert_consume(TokenType::RightParen)?;
+                Ok(expression)
             }
             TokenType::IntLiteral => {
                 let value = self
-                    .assert_consume(TokenType::IntLiteral)
+                    .assert_consume(TokenType::IntLiteral)?
                     .value
-                    .parse::<u64>()
-                    .unwrap();
+                    .replace('_', "")
+                    .parse::<u64>();
+
+                let value = match value {
+                    Ok(value) => value,
+                    Err(_) => return Err(self.error("Integer literal is too large")),
+                };
+
                 let mut primitive_type = PrimitiveType::UInt8;
 
                 if value > 2u64.pow(32) - 1 {
@@ -216,52 +628,363 @@ impl Parser {
                     primitive_type = PrimitiveType::UInt16;
                 }
 
-                AstNode::NumericLiteral(primitive_type, PrimitiveValue { uint64: value })
+                Ok(AstNode::NumericLiteral(
+                    primitive_type,
+                    PrimitiveValue { uint64: value },
+                ))
+            }
+            TokenType::FloatLiteral => {
+                let value = self
+                    .assert_consume(TokenType::FloatLiteral)?
+                    .value
+                    .replace('_', "")
+                    .parse::<f64>();
+
+                let value = match value {
+                    Ok(value) => value,
+                    Err(_) => return Err(self.error("Invalid float literal")),
+                };
+
+                Ok(AstNode::NumericLiteral(
+                    PrimitiveType::Float64,
+                    PrimitiveValue { float64: value },
+                ))
+            }
+            TokenType::BoolLiteral => {
+                let value = self.assert_consume(TokenType::BoolLiteral)?.value == "true";
+
+                Ok(AstNode::NumericLiteral(
+                    PrimitiveType::Bool,
+                    PrimitiveValue { uint8: value as u8 },
+                ))
+            }
+            TokenType::CharLiteral => {
+                let value = self
+                    .assert_consume(TokenType::CharLiteral)?
+                    .value
+                    .chars()
+                    .next()
+                    .unwrap() as u8;
+
+                Ok(AstNode::NumericLiteral(
+                    PrimitiveType::Char,
+                    PrimitiveValue {
+                        uint64: value as u64,
+                    },
+                ))
+            }
+            TokenType::LeftBrace => self.parse_block_expression(),
+            TokenType::StringLiteral => {
+                let value = self.assert_consume(TokenType::StringLiteral)?.value.clone();
+
+                Ok(AstNode::StringLiteral(value))
+            }
+            TokenType::Identifier
+                if current_token.value == "sizeof"
+                    && self.peek(1)?.token_type == TokenType::LeftParen
+                    && self.find_scope_var("sizeof").is_none() =>
+            {
+                self.parse_sizeof()
+            }
+            TokenType::Identifier if self.peek(1)?.token_type == TokenType::LeftParen => {
+                let function_call = self.parse_functioncall_expression()?;
+
+                if function_call.get_primitive_type() == PrimitiveType::Void {
+                    return Err(self.error("Void function call can't be used in an expression"));
+                }
+
+                Ok(function_call)
+            }
+            TokenType::Identifier if self.peek(1)?.token_type == TokenType::LeftBracket => {
+                let scope_var = self.parse_indexed_symbol()?;
+                self.mark_read(&scope_var.name);
+                let index = self.parse_index()?;
+
+                Ok(AstNode::Index(scope_var, Box::new(index)))
             }
             TokenType::Identifier => {
-                let identifier = self.assert_consume(TokenType::Identifier).value.clone();
-                let scope_var = self
-                    .find_scope_var(&identifier)
-                    .unwrap_or_else(|| panic!("Unknown identifier {}", identifier));
-                AstNode::Identifier(scope_var.clone())
+                let identifier_index = self.index;
+                self.assert_consume(TokenType::Identifier)?;
+                let scope_var = self.find_symbol(identifier_index, VARIABLE_SYMBOL_TYPES)?;
+                self.mark_read(&scope_var.name);
+
+                if scope_var.primitive_type.is_array() {
+                    return Err(
+                        self.error_at(identifier_index, "Arrays can only be used through an index")
+                    );
+                }
+
+                Ok(AstNode::Identifier(scope_var))
             }
             _ => unreachable!(),
         }
     }
 
+    /// Parses the builtin sizeof, which is the size of a type in bytes as a u64 literal
+    fn parse_sizeof(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::Identifier)?;
+        self.assert_consume(TokenType::LeftParen)?;
+
+        if !matches!(self.peek(0)?.token_type, TokenType::Type | TokenType::Star) {
+            return Err(self.error("sizeof expects a type"));
+        }
+
+        let primitive_type = self.parse_variable_type()?;
+        self.assert_consume(TokenType::RightParen)?;
+
+        Ok(AstNode::NumericLiteral(
+            PrimitiveType::UInt64,
+            PrimitiveValue {
+                uint64: (primitive_type.get_size() / 8) as u64,
+            },
+        ))
+    }
+
+    /// Converts an expression to the type it is assigned to. Literals take on
+    /// the destination type when their value fits and smaller values are widened.
+    /// Incompatible expressions are returned as is for the type check to report.
+    fn convert_expression(
+        &self,
+        mut expression: AstNode,
+        primitive_type: &PrimitiveType,
+        expression_index: usize,
+    ) -> ParseResult<AstNode> {
+        if primitive_type.is_integer() {
+            if matches!(expression, AstNode::BinaryOperation(..))
+                && is_constant_arithmetic(&expression, primitive_type)
+            {
+                expression = retype_constant_arithmetic(expression, primitive_type);
+            }
+
+            if let Some(value) = get_integer_literal(&expression) {
+                let (min, max) = get_integer_range(primitive_type);
+
+                if value < min || value > max {
+                    return Err(self.error_at(
+                        expression_index,
+                        &format!("Literal {} doesn't fit in {:?}", value, primitive_type),
+                    ));
+                }
+
+                expression = AstNode::NumericLiteral(
+                    primitive_type.clone(),
+                    PrimitiveValue {
+                        int64: value as i64,
+                    },
+                );
+            }
+        }
+
+        if *primitive_type == PrimitiveType::Float32 {
+            expression = make_literal_float32(expression);
+        }
+
+        let expression_type = expression.get_primitive_type();
+        if primitive_type.get_size() > expression_type.get_size()
+            && expression_type.is_compatible_with(primitive_type, true)
+        {
+            expression = AstNode::Widen(primitive_type.clone(), Box::new(expression));
+        }
+
+        Ok(expression)
+    }
+
+    /// Parses a unary expression followed by any number of `as` casts
+    fn parse_cast_expression(&mut self) -> ParseResult<AstNode> {
+        let mut expression = self.parse_unary_expression()?;
+
+        while !self.eof() && self.peek(0)?.token_type == TokenType::As {
+            self.assert_consume(TokenType::As)?;
+
+            let type_index = self.index;
+            let primitive_type = self.parse_variable_type()?;
+            let expression_type = expression.get_primitive_type();
+
+            // Any number can be cast to any other, but a bool is not a number
+            if (expression_type == PrimitiveType::Bool) != (primitive_type == PrimitiveType::Bool) {
+                return Err(self.error_at(
+                    type_index,
+                    &format!("Can't cast {:?} to {:?}", expression_type, primitive_type),
+                ));
+            }
+
+            expression = AstNode::Cast(primitive_type, Box::new(expression));
+        }
+
+        Ok(expression)
+    }
+
     /// Converts an expression of binary operators into an AST
     ///
     /// It uses the pratt parsing algorithm to recursively construct the
     /// AST with the correct precedence rules.
-    fn parse_expression(&mut self, precedence: OperatorPrecedence) -> AstNode {
+    fn parse_expression(&mut self, precedence: OperatorPrecedence) -> ParseResult<AstNode> {
+        let expression = self.parse_binary_expression(precedence)?;
+
+        // The conditional operator binds looser than every binary operator
+        if precedence != OperatorPrecedence::Zero
+            || self.eof()
+            || self.peek(0)?.token_type != TokenType::QuestionMark
+        {
+            return Ok(expression);
+        }
+
+        self.parse_ternary_expression(expression)
+    }
+
+    /// Parses the branches of `condition ? a : b`, the narrower branch is
+    /// widened to the type of the other one.
+    fn parse_ternary_expression(&mut self, condition: AstNode) -> ParseResult<AstNode> {
+        if condition.get_primitive_type() != PrimitiveType::Bool {
+            return Err(self.error("Condition of a conditional expression must be a boolean"));
+        }
+
+        self.assert_consume(TokenType::QuestionMark)?;
+        let mut then_value = self.parse_expression(OperatorPrecedence::Zero)?;
+        self.assert_consume(TokenType::Colon)?;
+        let else_index = self.index;
+        let mut else_value = self.parse_expression(OperatorPrecedence::Zero)?;
+
+        if then_value.get_primitive_type().is_signed() {
+            else_value = make_literal_signed(else_value);
+        } else if else_value.get_primitive_type().is_signed() {
+            then_value = make_literal_signed(then_value);
+        }
+
+        if then_value.get_primitive_type() == PrimitiveType::Float32 {
+            else_value = make_literal_float32(else_value);
+        } else if else_value.get_primitive_type() == PrimitiveType::Float32 {
+            then_value = make_literal_float32(then_value);
+        }
+
+        let then_type = then_value.get_primitive_type();
+        let else_type = else_value.get_primitive_type();
+
+        if then_type == PrimitiveType::Void || !then_type.is_compatible_with(&else_type, false) {
+            return Err(self.error_at(
+                else_index,
+                "Incompatible types in the branches of a conditional expression",
+            ));
+        }
+
+        match then_type.get_size().cmp(&else_type.get_size()) {
+            Ordering::Greater => else_value = AstNode::Widen(then_type, Box::new(else_value)),
+            Ordering::Less => then_value = AstNode::Widen(else_type, Box::new(then_value)),
+            _ => {}
+        }
+
+        Ok(AstNode::Ternary(
+            Box::new(condition),
+            Box::new(then_value),
+            Box::new(else_value),
+        ))
+    }
+
+    fn parse_binary_expression(&mut self, precedence: OperatorPrecedence) -> ParseResult<AstNode> {
         let break_condition = |token: &Token| {
             token.token_type == TokenType::SemiColon
                 || token.token_type == TokenType::RightParen
                 || token.token_type == TokenType::Comma
                 || token.token_type == TokenType::LeftBrace
+                || token.token_type == TokenType::RightBrace
+                || token.token_type == TokenType::RightBracket
+                || token.token_type == TokenType::QuestionMark
+                || token.token_type == TokenType::Colon
         };
 
-        let mut left = self.parse_unary_expression();
+        let mut left = self.parse_cast_expression()?;
+        // A single operand can only be a comparison when it is in parentheses,
+        // once operators are combined into it that is no longer the case
+        let mut left_is_operand = true;
 
-        let mut operator = self.peek(0);
+        let mut operator = self.peek(0)?;
 
         if break_condition(operator) {
-            return left;
+            return Ok(left);
         }
 
-        let mut operator_type = token_type_to_operator(operator.token_type);
+        let mut operator_type = self.parse_operator(operator)?;
         let mut current_precedence = get_operator_precedence(operator_type);
 
         while current_precedence > precedence {
-            self.consume();
+            // Chained comparisons like a < b < c would compare a bool with the last
+            // operand, parentheses make it clear this is what's intended
+            if is_comparison(operator_type)
+                && !left_is_operand
+                && matches!(&left, AstNode::BinaryOperation(left_operator, _, _, _) if is_comparison(*left_operator))
+            {
+                return Err(self.error(
+                    "Comparison operators can't be chained, use && to combine comparisons or add parentheses",
+                ));
+            }
+
+            let operator_span = self.span_at(self.index);
+            self.consume()?;
 
-            let mut right = self.parse_expression(current_precedence);
+            let mut right = self.parse_binary_expression(current_precedence)?;
+
+            if left.get_primitive_type().is_signed() {
+                right = make_literal_signed(right);
+            } else if right.get_primitive_type().is_signed() {
+                left = make_literal_signed(left);
+            }
+
+            if left.get_primitive_type() == PrimitiveType::Float32 {
+                right = make_literal_float32(right);
+            } else if right.get_primitive_type() == PrimitiveType::Float32 {
+                left = make_literal_float32(left);
+            }
 
             let left_type = left.get_primitive_type();
             let right_type = right.get_primitive_type();
 
+            if matches!(
+                operator_type,
+                BinaryOperationType::LogicalAnd | BinaryOperationType::LogicalOr
+            ) && (left_type != PrimitiveType::Bool || right_type != PrimitiveType::Bool)
+            {
+                return Err(self.error("Logical operators expect boolean operands"));
+            }
+
+            if matches!(
+                operator_type,
+                BinaryOperationType::BitAnd
+                    | BinaryOperationType::BitOr
+                    | BinaryOperationType::BitXor
+                    | BinaryOperationType::ShiftLeft
+                    | BinaryOperationType::ShiftRight
+            ) && (!left_type.is_integer() || !right_type.is_integer())
+            {
+                return Err(self.error("Bitwise operators expect integer operands"));
+            }
+
             if !left_type.is_compatible_with(&right_type, false) {
-                self.error("Incompatible types in expression");
+                return Err(self.error("Incompatible types in expression"));
+            }
+
+            if left_type == PrimitiveType::Bool
+                && matches!(
+                    operator_type,
+                    BinaryOperationType::LessThan
+                        | BinaryOperationType::LessThanOrEqual
+                        | BinaryOperationType::GreaterThan
+                        | BinaryOperationType::GreaterThanOrEqual
+                )
+            {
+                return Err(self.error("Booleans can only be compared for equality"));
+            }
+
+            if left_type.is_pointer()
+                && !matches!(
+                    operator_type,
+                    BinaryOperationType::Equals | BinaryOperationType::NotEquals
+                )
+            {
+                return Err(self.error("Pointers can only be compared for equality"));
+            }
+
+            if left_type.is_float() && !matches!(operator_type, BinaryOperationType::Add) {
+                return Err(self.error("Only addition is supported for float operands"));
             }
 
             match left_type.get_size().cmp(&right_type.get_size()) {
@@ -270,245 +993,1106 @@ impl Parser {
                 _ => {}
             }
 
-            left = AstNode::BinaryOperation(operator_type, Box::new(left), Box::new(right));
+            left = AstNode::BinaryOperation(
+                operator_type,
+                Box::new(left),
+                Box::new(right),
+                operator_span,
+            );
+            left_is_operand = false;
 
-            operator = self.peek(0);
+            operator = self.peek(0)?;
 
             if break_condition(operator) {
-                return left;
+                return Ok(left);
             }
 
-            operator_type = token_type_to_operator(operator.token_type);
+            operator_type = self.parse_operator(operator)?;
             current_precedence = get_operator_precedence(operator_type)
         }
 
-        left
+        Ok(left)
     }
 
-    fn parse_variable_type(&mut self) -> PrimitiveType {
-        let type_token = self.assert_consume(TokenType::Type);
-        type_token
-            .value
-            .parse::<PrimitiveType>()
-            .unwrap_or_else(|_| panic!("Unknown primitive type: {}", type_token.value))
+    fn parse_operator(&self, token: &Token) -> ParseResult<BinaryOperationType> {
+        match token_type_to_operator(token.token_type) {
+            Some(operator_type) => Ok(operator_type),
+            None => Err(self.error(&format!(
+                "Expected an operator but found {:?}",
+                token.token_type
+            ))),
+        }
     }
 
-    fn parse_variable_declaration(&mut self) -> AstNode {
-        self.assert_consume(TokenType::Var);
-        let name = self.assert_consume(TokenType::Identifier).value.clone();
-        self.assert_consume(TokenType::Colon);
-        let primitive_type = self.parse_variable_type();
-        self.assert_consume(TokenType::SemiColon);
+    fn parse_variable_type(&mut self) -> ParseResult<PrimitiveType> {
+        if self.peek(0)?.token_type == TokenType::Star {
+            self.assert_consume(TokenType::Star)?;
+            let primitive_type = self.parse_variable_type()?;
 
-        let symbol = self.add_to_scope(&name, primitive_type, Vec::new(), SymbolType::Variable);
-
-        AstNode::VariableDeclaration(symbol)
-    }
+            return Ok(PrimitiveType::Pointer(Box::new(primitive_type)));
+        }
 
-    fn parse_assignment(&mut self) -> AstNode {
-        let identifier_name = self.consume().value.clone();
-        self.assert_consume(TokenType::EqualSign);
+        let type_token = self.assert_consume(TokenType::Type)?;
+        let type_name = type_token.value.clone();
 
-        let mut expression = self.parse_expression(OperatorPrecedence::Zero);
-        self.consume();
+        let mut primitive_type = match type_name.parse::<PrimitiveType>() {
+            Ok(primitive_type) => primitive_type,
+            Err(_) => return Err(self.error(&format!("Unknown primitive type: {}", type_name))),
+        };
 
-        let scope_var = self
-            .find_scope_var(&identifier_name)
-            .unwrap_or_else(|| panic!("Unknown identifier: {}", identifier_name));
+        // The suffixes wrap the type before them, so `u8*[3]` is an array of pointers
+        while !self.eof() {
+            match self.peek(0)?.token_type {
+                TokenType::Star if self.is_pointer_suffix() => {
+                    self.assert_consume(TokenType::Star)?;
+                    primitive_type = PrimitiveType::Pointer(Box::new(primitive_type));
+                }
+                TokenType::LeftBracket => {
+                    if primitive_type.is_array() {
+                        return Err(self.error("Arrays of arrays are not supported"));
+                    }
 
-        if scope_var.primitive_type.get_size() > expression.get_primitive_type().get_size() {
-            expression = AstNode::Widen(scope_var.primitive_type, Box::new(expression));
+                    self.assert_consume(TokenType::LeftBracket)?;
+                    let length = match self
+                        .assert_consume(TokenType::IntLiteral)?
+                        .value
+                        .replace('_', "")
+                        .parse::<usize>()
+                    {
+                        Ok(length) if length > 0 => length,
+                        _ => return Err(self.error("Array length must be a positive integer")),
+                    };
+                    self.assert_consume(TokenType::RightBracket)?;
+
+                    primitive_type = PrimitiveType::Array(Box::new(primitive_type), length);
+                }
+                _ => break,
+            }
         }
 
-        AstNode::Assignment(scope_var.clone(), Box::new(expression))
+        Ok(primitive_type)
     }
 
-    fn parse_functioncall(&mut self) -> AstNode {
-        let function_name = self.assert_consume(TokenType::Identifier).value.clone();
+    /// Checks whether the stars after a type make it a pointer, like in `u8*`,
+    /// instead of multiplying a cast like in `x as u8 * y`
+    fn is_pointer_suffix(&self) -> bool {
+        let after_stars = self.tokens[self.index..]
+            .iter()
+            .find(|token| token.token_type != TokenType::Star);
+
+        // A block after the type is the body of a function returning a pointer
+        match after_stars {
+            Some(token) => {
+                token.token_type == TokenType::LeftBrace || !is_operand_start(token.token_type)
+            }
+            None => true,
+        }
+    }
 
-        self.assert_consume(TokenType::LeftParen);
+    /// Parses the index of an array access, which has to be an unsigned integer
+    fn parse_index(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::LeftBracket)?;
+        let index = self.parse_expression(OperatorPrecedence::Zero)?;
+        self.assert_consume(TokenType::RightBracket)?;
 
-        //TODO: fix this clone mess
-        let symbol = self
-            .find_scope_var(&function_name)
-            .unwrap_or_else(|| panic!("Unknown function: {}", function_name))
-            .clone();
+        if !index.get_primitive_type().is_unsigned() {
+            return Err(self.error("Array index must be an unsigned integer"));
+        }
 
-        let mut params: Vec<AstNode> = Vec::new();
+        Ok(index)
+    }
 
-        let mut param_index: usize = 0;
+    /// Looks up the array in front of an index
+    fn parse_indexed_symbol(&mut self) -> ParseResult<Symbol> {
+        let identifier_index = self.index;
+        self.assert_consume(TokenType::Identifier)?;
+        let scope_var = self.find_symbol(identifier_index, VARIABLE_SYMBOL_TYPES)?;
 
-        loop {
-            if self.peek(0).token_type == TokenType::RightParen {
-                break;
-            }
+        if !scope_var.primitive_type.is_array() {
+            return Err(self.error_at(
+                identifier_index,
+                &format!("{} is not an array", scope_var.name),
+            ));
+        }
 
-            let expression = self.parse_expression(OperatorPrecedence::Zero);
+        Ok(scope_var)
+    }
 
-            let expression_type = expression.get_primitive_type();
-            if !expression_type.is_compatible_with(&symbol.parameter_types[param_index], true) {
-                self.error("Incompatible types in function call");
+    fn parse_variable_declaration(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::Var)?;
+        let name_index = self.index;
+        let name = self.assert_consume(TokenType::Identifier)?.value.clone();
+
+        let mut primitive_type = None;
+
+        if self.peek(0)?.token_type == TokenType::Colon {
+            self.assert_consume(TokenType::Colon)?;
+            primitive_type = Some(self.parse_variable_type()?);
+        }
+
+        let mut initializer = None;
+        let initializer_index = self.index + 1;
+
+        if self.peek(0)?.token_type == TokenType::EqualSign {
+            self.assert_consume(TokenType::EqualSign)?;
+            initializer = Some(self.parse_expression(OperatorPrecedence::Zero)?);
+        }
+
+        self.assert_consume(TokenType::SemiColon)?;
+
+        let primitive_type = match (primitive_type, &initializer) {
+            (Some(primitive_type), _) => primitive_type,
+            (None, Some(initializer)) => initializer.get_primitive_type(),
+            (None, None) => {
+                return Err(self.error_at(
+                    name_index,
+                    &format!("Variable {} needs either a type or an initializer", name),
+                ));
             }
+        };
 
-            params.push(expression);
-            param_index += 1;
+        if primitive_type.is_array() && initializer.is_some() {
+            return Err(self.error_at(
+                name_index,
+                &format!("Array {} can't have an initializer", name),
+            ));
+        }
+
+        let initializer = match initializer {
+            Some(expression) => {
+                let expression =
+                    self.convert_expression(expression, &primitive_type, initializer_index)?;
+                self.check_assignment(&primitive_type, &expression, name_index)?;
+
+                Some(Box::new(expression))
+            }
+            None => None,
+        };
+
+        // Variables outside of any function live in the data section
+        if self.scope.len() == 1 {
+            if let Some(initializer) = &initializer {
+                if initializer.get_constant_bits(&primitive_type).is_none() {
+                    return Err(self.error_at(
+                        initializer_index,
+                        &format!("Global variable {} needs a constant initializer", name),
+                    ));
+                }
+            }
+
+            let symbol = self.add_to_scope(
+                name_index,
+                &name,
+                primitive_type,
+                Vec::new(),
+                SymbolType::Global,
+            )?;
+
+            return Ok(AstNode::GlobalDeclaration(symbol, initializer));
+        }
+
+        let symbol = self.add_to_scope(
+            name_index,
+            &name,
+            primitive_type,
+            Vec::new(),
+            SymbolType::Variable,
+        )?;
+        self.scope
+            .last_mut()
+            .unwrap()
+            .unread
+            .insert(name, name_index);
+
+        Ok(AstNode::VariableDeclaration(
+            symbol,
+            initializer,
+            self.span_at(name_index),
+        ))
+    }
+
+    /// Checks that the value of an expression can be stored in a variable of the
+    /// given type, the error points at the token at the given index
+    fn check_assignment(
+        &self,
+        primitive_type: &PrimitiveType,
+        expression: &AstNode,
+        token_index: usize,
+    ) -> ParseResult<()> {
+        let expression_type = expression.get_primitive_type();
+
+        if !expression_type.is_compatible_with(primitive_type, true) {
+            return Err(self.error_at(
+                token_index,
+                &format!(
+                    "Incompatible types in assignment, {:?} = {:?}",
+                    primitive_type, expression_type
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn parse_assignment(&mut self) -> ParseResult<AstNode> {
+        let assignment = self.parse_assignment_expression()?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        Ok(assignment)
+    }
+
+    fn parse_assignment_expression(&mut self) -> ParseResult<AstNode> {
+        let identifier_index = self.index;
+        self.assert_consume(TokenType::Identifier)?;
+        let scope_var = self.find_symbol(identifier_index, VARIABLE_SYMBOL_TYPES)?;
+
+        self.assert_consume(TokenType::EqualSign)?;
+
+        // Assignments are right associative, `a = b = 0` first assigns 0 to b
+        // and then assigns the value of that assignment to a
+        let expression_index = self.index;
+        let expression = if self.peek(0)?.token_type == TokenType::Identifier
+            && self.peek(1)?.token_type == TokenType::EqualSign
+        {
+            self.parse_assignment_expression()?
+        } else {
+            self.parse_expression(OperatorPrecedence::Zero)?
+        };
+        let expression =
+            self.convert_expression(expression, &scope_var.primitive_type, expression_index)?;
+        self.check_assignment(&scope_var.primitive_type, &expression, identifier_index)?;
+
+        Ok(AstNode::Assignment(
+            scope_var,
+            Box::new(expression),
+            self.span_at(identifier_index),
+        ))
+    }
+
+    fn parse_dereference_assignment(&mut self) -> ParseResult<AstNode> {
+        let assignment_index = self.index;
+        let pointer = match self.parse_unary_expression()? {
+            AstNode::Dereference(pointer) => pointer,
+            _ => return Err(self.error("Expected a dereference on the left side of an assignment")),
+        };
+
+        self.assert_consume(TokenType::EqualSign)?;
+
+        let expression_index = self.index;
+        let expression = self.parse_expression(OperatorPrecedence::Zero)?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        let primitive_type = match pointer.get_primitive_type() {
+            PrimitiveType::Pointer(primitive_type) => *primitive_type,
+            _ => unreachable!(),
+        };
+
+        let expression = self.convert_expression(expression, &primitive_type, expression_index)?;
+        self.check_assignment(&primitive_type, &expression, assignment_index)?;
+
+        Ok(AstNode::DereferenceAssignment(
+            pointer,
+            Box::new(expression),
+        ))
+    }
+
+    fn parse_index_assignment(&mut self) -> ParseResult<AstNode> {
+        let assignment_index = self.index;
+        let scope_var = self.parse_indexed_symbol()?;
+        let index = self.parse_index()?;
+
+        self.assert_consume(TokenType::EqualSign)?;
+
+        let expression_index = self.index;
+        let expression = self.parse_expression(OperatorPrecedence::Zero)?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        let primitive_type = match &scope_var.primitive_type {
+            PrimitiveType::Array(element_type, _) => *element_type.clone(),
+            _ => unreachable!(),
+        };
+
+        let expression = self.convert_expression(expression, &primitive_type, expression_index)?;
+        self.check_assignment(&primitive_type, &expression, assignment_index)?;
+
+        Ok(AstNode::IndexAssignment(
+            scope_var,
+            Box::new(index),
+            Box::new(expression),
+        ))
+    }
+
+    fn parse_functioncall(&mut self) -> ParseResult<AstNode> {
+        let function_call = self.parse_functioncall_expression()?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        Ok(function_call)
+    }
+
+    fn parse_functioncall_expression(&mut self) -> ParseResult<AstNode> {
+        let function_index = self.index;
+        let function_name = self.assert_consume(TokenType::Identifier)?.value.clone();
+        let symbol = self.find_symbol(function_index, &[SymbolType::Function])?;
+
+        self.assert_consume(TokenType::LeftParen)?;
+
+        let mut params: Vec<AstNode> = Vec::new();
+
+        loop {
+            if self.peek(0)?.token_type == TokenType::RightParen {
+                break;
+            }
+
+            params.push(self.parse_expression(OperatorPrecedence::Zero)?);
 
-            if self.peek(0).token_type == TokenType::RightParen {
+            // A trailing comma is allowed, the check at the start of the loop
+            // ends the list when the parenthesis follows right after it
+            if self.peek(0)?.token_type == TokenType::RightParen {
                 break;
             } else {
-                self.assert_consume(TokenType::Comma);
+                self.assert_consume(TokenType::Comma)?;
             }
         }
 
-        self.assert_consume(TokenType::RightParen);
-        self.assert_consume(TokenType::SemiColon);
+        self.assert_consume(TokenType::RightParen)?;
+
+        if params.len() != symbol.parameter_types.len() {
+            return Err(self.error_at(
+                function_index,
+                &format!(
+                    "{} expects {} arguments, found {}",
+                    function_name,
+                    symbol.parameter_types.len(),
+                    params.len()
+                ),
+            ));
+        }
 
-        AstNode::FunctionCall(function_name, params)
+        for (param, parameter_type) in params.iter().zip(&symbol.parameter_types) {
+            if !param
+                .get_primitive_type()
+                .is_compatible_with(parameter_type, true)
+            {
+                return Err(self.error_at(function_index, "Incompatible types in function call"));
+            }
+        }
+
+        Ok(AstNode::FunctionCall(symbol, params))
     }
 
-    fn parse_block(&mut self) -> AstNode {
-        self.scope.push(Scope::new());
+    /// Parses a call to the builtin printf, which takes a format string and up
+    /// to five integer arguments.
+    fn parse_printf(&mut self) -> ParseResult<AstNode> {
+        let printf_index = self.index;
+        self.assert_consume(TokenType::Identifier)?;
+        self.assert_consume(TokenType::LeftParen)?;
 
-        let mut children: Vec<AstNode> = vec![];
+        if self.peek(0)?.token_type != TokenType::StringLiteral {
+            return Err(self.error("The format of printf must be a string literal"));
+        }
+
+        let mut params = vec![self.parse_unary_expression()?];
 
-        self.assert_consume(TokenType::LeftBrace);
+        while self.peek(0)?.token_type == TokenType::Comma {
+            self.assert_consume(TokenType::Comma)?;
 
-        while self.peek(0).token_type != TokenType::RightBrace {
-            let node = self.parse_single();
-            children.push(node);
+            let param_index = self.index;
+            let param = self.parse_expression(OperatorPrecedence::Zero)?;
+            let param_type = param.get_primitive_type();
+
+            if param_type.is_float() || param_type.is_array() || param_type == PrimitiveType::Void {
+                return Err(self.error_at(
+                    param_index,
+                    &format!("printf can't print a value of type {:?}", param_type),
+                ));
+            }
+
+            params.push(param);
         }
 
-        self.assert_consume(TokenType::RightBrace);
+        self.assert_consume(TokenType::RightParen)?;
+        self.assert_consume(TokenType::SemiColon)?;
 
-        self.scope.pop();
+        if params.len() - 1 > PRINTF_ARGUMENT_LIMIT {
+            return Err(self.error_at(
+                printf_index,
+                &format!(
+                    "printf takes at most {} arguments after the format string",
+                    PRINTF_ARGUMENT_LIMIT
+                ),
+            ));
+        }
 
-        AstNode::Block(children)
+        Ok(AstNode::Printf(params))
     }
 
-    fn parse_if(&mut self) -> AstNode {
-        self.assert_consume(TokenType::If);
+    /// Parses a call to the builtin assert, which takes a single bool
+    fn parse_assert(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::Identifier)?;
+        self.assert_consume(TokenType::LeftParen)?;
+
+        let condition_index = self.index;
+        let condition = self.parse_expression(OperatorPrecedence::Zero)?;
+        let condition_type = condition.get_primitive_type();
+
+        if condition_type != PrimitiveType::Bool {
+            return Err(self.error_at(
+                condition_index,
+                &format!(
+                    "assert expects a Bool condition, found {:?}",
+                    condition_type
+                ),
+            ));
+        }
+
+        self.assert_consume(TokenType::RightParen)?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        Ok(AstNode::Assert(Box::new(condition)))
+    }
+
+    /// Pushes the scope of a block nested inside of a function
+    ///
+    /// Its variables are stored after the ones of the enclosing scopes, so
+    /// they never share a stack slot with a variable that is still alive.
+    fn push_block_scope(&mut self) {
+        let mut block_scope = Scope::new();
+        if self.scope.len() > 1 {
+            block_scope.last_offset = self.scope[self.scope.len() - 1].last_offset;
+        }
+        self.scope.push(block_scope);
+    }
 
-        let expression = self.parse_expression(OperatorPrecedence::Zero);
-        if expression.get_primitive_type() != PrimitiveType::Bool {
-            self.error("If statement should contain a boolean expression");
+    fn parse_block(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::LeftBrace)?;
+
+        self.push_block_scope();
+
+        let mut children: Vec<AstNode> = vec![];
+        let mut diverged = false;
+        let mut reported_unreachable = false;
+
+        while !self.eof() && self.peek(0)?.token_type != TokenType::RightBrace {
+            let statement_index = self.index;
+
+            match self.parse_statement() {
+                Ok(node) => {
+                    // Only the first unreachable statement is reported
+                    if diverged && !reported_unreachable {
+                        let warning = self.error_at(statement_index, "unreachable statement");
+                        self.warnings.push(warning);
+                        reported_unreachable = true;
+                    }
+                    diverged = diverged || node.diverges();
+                    children.push(node);
+                }
+                Err(error) => self.recover(error),
+            }
         }
 
-        let code = self.parse_block();
+        self.pop_scope();
+
+        self.assert_consume(TokenType::RightBrace)?;
+
+        Ok(AstNode::Block(children, None))
+    }
+
+    /// Checks whether the next part of a block expression is the expression at
+    /// its end, which is the case when the closing brace of the block comes
+    /// before the semicolon of a statement
+    ///
+    /// A nested block at the start is a statement, unless the closing brace
+    /// follows right after it.
+    fn is_block_result(&self) -> bool {
+        let first_type = self.tokens[self.index].token_type;
+        if matches!(
+            first_type,
+            TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Var
+                | TokenType::Function
+                | TokenType::Extern
+                | TokenType::Return
+        ) {
+            return false;
+        }
+
+        let mut depth = 0;
+        for (offset, token) in self.tokens[self.index..].iter().enumerate() {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket => depth -= 1,
+                TokenType::RightBrace if depth == 0 => return true,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 && first_type == TokenType::LeftBrace {
+                        return self
+                            .tokens
+                            .get(self.index + offset + 1)
+                            .is_some_and(|token| token.token_type == TokenType::RightBrace);
+                    }
+                }
+                TokenType::SemiColon if depth == 0 => return false,
+                _ => {}
+            }
+        }
+
+        false
+    }
+
+    /// Parses a block whose value is the expression at its end, like
+    /// `{ var t: u32 = 3; t + 1 }`
+    fn parse_block_expression(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::LeftBrace)?;
+
+        self.push_block_scope();
+
+        let mut children: Vec<AstNode> = vec![];
+
+        let result = loop {
+            if self.peek(0)?.token_type == TokenType::RightBrace {
+                return Err(self.error("A block expression has to end with an expression"));
+            }
+
+            if self.is_block_result() {
+                break self.parse_expression(OperatorPrecedence::Zero)?;
+            }
+
+            children.push(self.parse_statement()?);
+        };
+
+        self.pop_scope();
+
+        self.assert_consume(TokenType::RightBrace)?;
+
+        if result.get_primitive_type() == PrimitiveType::Void {
+            return Err(self.error("The value of a block expression can't be void"));
+        }
+
+        Ok(AstNode::Block(children, Some(Box::new(result))))
+    }
+
+    /// Parses the condition of a statement, which has to be a boolean expression
+    fn parse_condition(&mut self, statement: &str) -> ParseResult<AstNode> {
+        let condition_index = self.index;
+        let condition = self.parse_expression(OperatorPrecedence::Zero)?;
+        let condition_type = condition.get_primitive_type();
+
+        if condition_type != PrimitiveType::Bool {
+            return Err(self.error_at(
+                condition_index,
+                &format!(
+                    "{} statement condition should be a boolean expression, found {:?}",
+                    statement, condition_type
+                ),
+            ));
+        }
+
+        Ok(condition)
+    }
+
+    fn parse_if(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::If)?;
+
+        let expression = self.parse_condition("If")?;
+
+        let code = self.parse_block()?;
 
         let mut else_statement: Option<Box<AstNode>> = None;
 
-        if self.peek(0).token_type == TokenType::Else {
-            self.assert_consume(TokenType::Else);
-            else_statement = Some(Box::new(self.parse_block()));
+        if !self.eof() && self.peek(0)?.token_type == TokenType::Else {
+            self.assert_consume(TokenType::Else)?;
+
+            // An else if chain is parsed as a nested if statement in the else branch
+            if self.peek(0)?.token_type == TokenType::If {
+                else_statement = Some(Box::new(self.parse_if()?));
+            } else {
+                else_statement = Some(Box::new(self.parse_block()?));
+            }
+        }
+
+        Ok(AstNode::If(
+            Box::new(expression),
+            Box::new(code),
+            else_statement,
+        ))
+    }
+
+    fn parse_while(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::While)?;
+
+        let expression = self.parse_condition("While")?;
+
+        self.loop_depth += 1;
+        let code = self.parse_block()?;
+        self.loop_depth -= 1;
+
+        Ok(AstNode::While(Box::new(expression), Box::new(code)))
+    }
+
+    fn parse_for(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::For)?;
+        self.assert_consume(TokenType::LeftParen)?;
+
+        // Variables declared in the header are only visible inside the loop
+        self.push_block_scope();
+
+        let init = match self.peek(0)?.token_type {
+            TokenType::Var => self.parse_variable_declaration()?,
+            _ => self.parse_assignment()?,
+        };
+
+        let condition = self.parse_condition("For")?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        let update = match self.peek(1)?.token_type {
+            TokenType::LeftParen => self.parse_functioncall_expression()?,
+            _ => self.parse_assignment_expression()?,
+        };
+        self.assert_consume(TokenType::RightParen)?;
+
+        self.loop_depth += 1;
+        let code = self.parse_block()?;
+        self.loop_depth -= 1;
+
+        self.pop_scope();
+
+        Ok(AstNode::For(
+            Box::new(init),
+            Box::new(condition),
+            Box::new(update),
+            Box::new(code),
+        ))
+    }
+
+    fn parse_break(&mut self) -> ParseResult<AstNode> {
+        let span = self.span_at(self.index);
+        self.assert_consume(TokenType::Break)?;
+
+        if self.loop_depth == 0 {
+            return Err(self.error("Break statement outside of a loop"));
         }
 
-        AstNode::If(Box::new(expression), Box::new(code), else_statement)
+        self.assert_consume(TokenType::SemiColon)?;
+
+        Ok(AstNode::Break(span))
     }
 
-    fn parse_while(&mut self) -> AstNode {
-        self.assert_consume(TokenType::While);
+    fn parse_continue(&mut self) -> ParseResult<AstNode> {
+        let span = self.span_at(self.index);
+        self.assert_consume(TokenType::Continue)?;
 
-        let expression = self.parse_expression(OperatorPrecedence::Zero);
-        if expression.get_primitive_type() != PrimitiveType::Bool {
-            self.error("While statement condition should be a boolean expression");
+        if self.loop_depth == 0 {
+            return Err(self.error("Continue statement outside of a loop"));
         }
 
-        let code = self.parse_block();
+        self.assert_consume(TokenType::SemiColon)?;
 
-        AstNode::While(Box::new(expression), Box::new(code))
+        Ok(AstNode::Continue(span))
     }
 
-    fn parse_parameter_list(&mut self) -> Vec<PrimitiveType> {
+    fn parse_return(&mut self) -> ParseResult<AstNode> {
+        let return_index = self.index;
+        self.assert_consume(TokenType::Return)?;
+
+        let return_type = match self.function_return_type.clone() {
+            Some(return_type) => return_type,
+            None => {
+                return Err(self.error_at(return_index, "Return statement outside of a function"))
+            }
+        };
+
+        if self.peek(0)?.token_type == TokenType::SemiColon {
+            self.assert_consume(TokenType::SemiColon)?;
+
+            if return_type != PrimitiveType::Void {
+                return Err(self.error_at(
+                    return_index,
+                    &format!("Expected a return value of type {:?}", return_type),
+                ));
+            }
+
+            return Ok(AstNode::Return(None));
+        }
+
+        let expression_index = self.index;
+        let mut expression = self.parse_expression(OperatorPrecedence::Zero)?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        let expression_type = expression.get_primitive_type();
+
+        if return_type == PrimitiveType::Void
+            || !expression_type.is_compatible_with(&return_type, true)
+        {
+            return Err(self.error_at(
+                expression_index,
+                &format!(
+                    "Incompatible return type, expected {:?} but found {:?}",
+                    return_type, expression_type
+                ),
+            ));
+        }
+
+        if return_type.get_size() > expression_type.get_size() {
+            expression = AstNode::Widen(return_type, Box::new(expression));
+        }
+
+        Ok(AstNode::Return(Some(Box::new(expression))))
+    }
+
+    fn parse_parameter_list(&mut self) -> ParseResult<Vec<PrimitiveType>> {
         let mut parameter_types: Vec<PrimitiveType> = Vec::new();
 
         let mut param_index = 0;
 
         loop {
-            if self.peek(0).token_type == TokenType::RightParen {
+            if self.peek(0)?.token_type == TokenType::RightParen {
                 break;
             }
 
+            if self.peek(0)?.token_type != TokenType::Identifier {
+                return Err(self.error("Expected a parameter"));
+            }
+
+            let name_index = self.index;
             //TODO: try and remove this clone
-            let param_name = &self.assert_consume(TokenType::Identifier).value.clone();
-            self.assert_consume(TokenType::Colon);
-            let param_type = self.parse_variable_type();
+            let param_name = &self.assert_consume(TokenType::Identifier)?.value.clone();
+            self.assert_consume(TokenType::Colon)?;
+            let param_type = self.parse_variable_type()?;
+
+            if param_type.is_float() {
+                return Err(self.error("Float parameters are not supported yet"));
+            }
+
+            if param_type.is_array() {
+                return Err(self.error("Array parameters are not supported"));
+            }
 
-            parameter_types.push(param_type);
+            parameter_types.push(param_type.clone());
 
             self.add_to_scope_with_offset(
-                &param_name,
+                name_index,
+                param_name,
                 param_type,
                 Vec::new(),
                 SymbolType::FunctionParameter,
                 param_index,
-            );
+            )?;
 
             param_index += 1;
 
-            if self.peek(0).token_type == TokenType::RightParen {
+            // A trailing comma is allowed, the check at the start of the loop
+            // ends the list when the parenthesis follows right after it
+            if self.peek(0)?.token_type == TokenType::RightParen {
                 break;
             } else {
-                self.assert_consume(TokenType::Comma);
+                self.assert_consume(TokenType::Comma)?;
             }
         }
 
-        parameter_types
+        Ok(parameter_types)
     }
 
-    fn parse_function(&mut self) -> AstNode {
-        self.assert_consume(TokenType::Function);
-        let function_name = self.assert_consume(TokenType::Identifier).value.clone();
-        self.assert_consume(TokenType::LeftParen);
+    /// Parses the name, parameters and return type of a function. The parameters
+    /// are added to a new scope which is left on the scope stack.
+    fn parse_function_signature(
+        &mut self,
+    ) -> ParseResult<(String, Vec<PrimitiveType>, PrimitiveType)> {
+        self.assert_consume(TokenType::Function)?;
+        let function_name = self.assert_consume(TokenType::Identifier)?.value.clone();
+        self.assert_consume(TokenType::LeftParen)?;
+
+        // The parameters live in their own scope around the function body
+        self.scope.push(Scope::new());
 
-        let parameter_types = self.parse_parameter_list();
-        self.assert_consume(TokenType::RightParen);
-        let code = self.parse_block();
+        let parameter_types = self.parse_parameter_list()?;
+        self.assert_consume(TokenType::RightParen)?;
 
+        // Parameters are spilled to their own area, the locals start after it
+        self.scope.last_mut().unwrap().last_offset = 0;
+
+        let mut return_type = PrimitiveType::Void;
+
+        if self.peek(0)?.token_type == TokenType::Colon {
+            self.assert_consume(TokenType::Colon)?;
+            return_type = self.parse_variable_type()?;
+
+            if return_type.is_float() {
+                return Err(self.error("Float return types are not supported yet"));
+            }
+
+            if return_type.is_array() {
+                return Err(self.error("Array return types are not supported"));
+            }
+        }
+
+        Ok((function_name, parameter_types, return_type))
+    }
+
+    /// Registers the signatures of all top level functions, so they can be
+    /// called before the point where they are defined.
+    fn declare_functions(&mut self) {
+        let mut depth = 0;
+
+        while !self.eof() {
+            match self.tokens[self.index].token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth -= 1,
+                TokenType::Function if depth == 0 => {
+                    let function_index = self.index;
+                    let scope_count = self.scope.len();
+
+                    let signature = self.parse_function_signature();
+                    self.scope.truncate(scope_count);
+
+                    // Errors are reported once the function is parsed for real
+                    if let Ok((name, parameter_types, return_type)) = signature {
+                        if self
+                            .add_to_scope(
+                                function_index + 1,
+                                &name,
+                                return_type,
+                                parameter_types,
+                                SymbolType::Function,
+                            )
+                            .is_ok()
+                        {
+                            self.declared_functions.insert(function_index);
+                        }
+                    }
+
+                    self.index = function_index;
+                }
+                _ => {}
+            }
+
+            self.index += 1;
+        }
+
+        self.index = 0;
+    }
+
+    /// Adds a function to the scope around its parameter scope, unless it was
+    /// already declared up front.
+    fn declare_function(
+        &mut self,
+        function_index: usize,
+        name: &str,
+        parameter_types: Vec<PrimitiveType>,
+        return_type: PrimitiveType,
+    ) -> ParseResult<Symbol> {
+        if self.declared_functions.remove(&function_index) {
+            return Ok(self.scope[0].get(name).unwrap().clone());
+        }
+
+        let parameter_scope = self.scope.pop().unwrap();
+        // The name follows right after the fn keyword
         let symbol = self.add_to_scope(
-            &function_name,
-            PrimitiveType::Void,
+            function_index + 1,
+            name,
+            return_type,
             parameter_types,
             SymbolType::Function,
         );
-        AstNode::Function(symbol, Box::new(code))
+        self.scope.push(parameter_scope);
+
+        symbol
+    }
+
+    fn parse_extern_function(&mut self) -> ParseResult<AstNode> {
+        self.assert_consume(TokenType::Extern)?;
+
+        let function_index = self.index;
+        let (function_name, parameter_types, return_type) = self.parse_function_signature()?;
+        self.assert_consume(TokenType::SemiColon)?;
+
+        let symbol =
+            self.declare_function(function_index, &function_name, parameter_types, return_type)?;
+        self.scope.pop();
+
+        Ok(AstNode::ExternFunction(symbol))
+    }
+
+    fn parse_function(&mut self) -> ParseResult<AstNode> {
+        let function_index = self.index;
+        let (function_name, parameter_types, return_type) = self.parse_function_signature()?;
+
+        // The value returned by main becomes the exit status of the process
+        if function_name == "main"
+            && return_type != PrimitiveType::Void
+            && return_type != PrimitiveType::Int32
+        {
+            return Err(self.error_at(
+                function_index,
+                "The main function can only return i32 or nothing",
+            ));
+        }
+
+        // The function is declared before its body is parsed so it can call itself
+        let symbol = self.declare_function(
+            function_index,
+            &function_name,
+            parameter_types,
+            return_type.clone(),
+        )?;
+
+        // Loops around a nested function don't extend into its body
+        let loop_depth = self.loop_depth;
+
+        self.function_return_type = Some(return_type);
+        self.loop_depth = 0;
+        let code = self.parse_block()?;
+        self.function_return_type = None;
+        self.loop_depth = loop_depth;
+
+        self.scope.pop();
+
+        if symbol.primitive_type != PrimitiveType::Void && !code.always_returns() {
+            return Err(self.error_at(
+                function_index,
+                &format!(
+                    "Function {} doesn't return a value on every path",
+                    function_name
+                ),
+            ));
+        }
+
+        Ok(AstNode::Function(symbol, Box::new(code)))
     }
 
-    fn parse_single(&mut self) -> AstNode {
-        let next_token: &Token = self.peek(0);
+    fn parse_single(&mut self) -> ParseResult<AstNode> {
+        let next_token: &Token = self.peek(0)?;
         match next_token.token_type {
             TokenType::LeftBrace => self.parse_block(),
             TokenType::If => self.parse_if(),
             TokenType::While => self.parse_while(),
+            TokenType::For => self.parse_for(),
+            TokenType::Break => self.parse_break(),
+            TokenType::Continue => self.parse_continue(),
             TokenType::Var => self.parse_variable_declaration(),
             TokenType::Function => self.parse_function(),
+            TokenType::Extern => self.parse_extern_function(),
+            TokenType::Return => self.parse_return(),
+            TokenType::Star => self.parse_dereference_assignment(),
             TokenType::Identifier => {
-                let next_token_type = self.peek(1).token_type;
+                let next_token_type = self.peek(1)?.token_type;
                 match next_token_type {
+                    TokenType::LeftParen
+                        if next_token.value == "printf"
+                            && self.find_scope_var("printf").is_none() =>
+                    {
+                        self.parse_printf()
+                    }
+                    TokenType::LeftParen
+                        if next_token.value == "assert"
+                            && self.find_scope_var("assert").is_none() =>
+                    {
+                        self.parse_assert()
+                    }
                     TokenType::LeftParen => self.parse_functioncall(),
                     TokenType::EqualSign => self.parse_assignment(),
-                    _ => {
-                        self.error(&format!(
-                            "Unexpected token {:?} after identifier",
-                            next_token_type
-                        ));
-                        unreachable!();
-                    }
+                    TokenType::LeftBracket => self.parse_index_assignment(),
+                    _ => Err(self.error(&format!(
+                        "Unexpected token {:?} after identifier",
+                        next_token_type
+                    ))),
                 }
             }
-            _ => {
-                self.error(&format!("Unexpected token: {:?}", next_token));
-                unreachable!();
-            }
+            _ => Err(self.error(&format!("Unexpected token: {:?}", next_token))),
+        }
+    }
+
+    /// Parses a single statement and restores the parser state when it fails
+    ///
+    /// This makes sure an error deep inside a statement doesn't leave scopes
+    /// or loop and function information behind for the statements after it.
+    fn parse_statement(&mut self) -> ParseResult<AstNode> {
+        let scope_count = self.scope.len();
+        let function_return_type = self.function_return_type.clone();
+        let loop_depth = self.loop_depth;
+
+        let result = self.parse_single();
+
+        if result.is_err() {
+            self.scope.truncate(scope_count);
+            self.function_return_type = function_return_type;
+            self.loop_depth = loop_depth;
         }
+
+        result
     }
 
-    pub fn parse(&mut self) -> AstNode {
+    fn parse_top_level(
+        &mut self,
+        nodes: &mut Vec<AstNode>,
+        statements: &mut Vec<AstNode>,
+    ) -> ParseResult<()> {
+        let token_type = self.peek(0)?.token_type;
+        let is_function = matches!(token_type, TokenType::Function | TokenType::Extern);
+
+        // Top level statements end up in a synthesized main function
+        if is_function && self.peek(1)?.value == "main" && !statements.is_empty() {
+            return Err(
+                self.error("Cannot define a main function when there are top level statements")
+            );
+        }
+        if !is_function && token_type != TokenType::Var && self.find_scope_var("main").is_some() {
+            return Err(self.error("Top level statements are not allowed next to a main function"));
+        }
+
+        match self.parse_statement()? {
+            node @ (AstNode::Function(..)
+            | AstNode::ExternFunction(..)
+            | AstNode::GlobalDeclaration(..)) => nodes.push(node),
+            node => statements.push(node),
+        }
+
+        Ok(())
+    }
+
+    pub fn parse(&mut self) -> Result<AstNode, Vec<CompileError>> {
         let mut nodes: Vec<AstNode> = Vec::new();
+        let mut statements: Vec<AstNode> = Vec::new();
+
+        self.declare_functions();
 
         while !self.eof() {
-            nodes.push(self.parse_single());
+            if let Err(error) = self.parse_top_level(&mut nodes, &mut statements) {
+                self.recover(error);
+
+                // A stray closing brace can't end a block at the top level
+                if !self.eof() && self.tokens[self.index].token_type == TokenType::RightBrace {
+                    self.index += 1;
+                }
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+
+        // An empty file, or one with only comments, still links into a program
+        if nodes.is_empty() && statements.is_empty() {
+            self.warnings.push(CompileError::new(
+                1,
+                1,
+                "empty program, no code was generated besides an empty main",
+            ));
+        }
+
+        // A program without a main function runs its top level statements, if any
+        if self.find_scope_var("main").is_none() {
+            let symbol =
+                self.scope[0].add("main", PrimitiveType::Void, vec![], SymbolType::Function);
+            nodes.push(AstNode::Function(
+                symbol,
+                Box::new(AstNode::Block(statements, None)),
+            ));
         }
 
-        AstNode::Block(nodes)
+        Ok(AstNode::Block(nodes, None))
     }
 }
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum SymbolType {
     Variable,
+    Global,
     Function,
     FunctionParameter,
 }
@@ -21,6 +22,8 @@ pub struct Symbol {
 pub struct Scope {
     pub symbols: HashMap<String, Symbol>,
     pub last_offset: i32,
+    /// The local variables which weren't read yet, with the token index of their declaration
+    pub unread: HashMap<String, usize>,
 }
 
 impl Scope {
@@ -28,14 +31,21 @@ impl Scope {
         Scope {
             symbols: HashMap::new(),
             last_offset: 0,
+            unread: HashMap::new(),
         }
     }
 
     pub fn get(&self, name: &str) -> Option<&Symbol> {
-        //TODO: add symbol type check
         self.symbols.get(name)
     }
 
+    /// Looks up a symbol, ignoring it when it isn't one of the given symbol types
+    pub fn get_of_type(&self, name: &str, symbol_types: &[SymbolType]) -> Option<&Symbol> {
+        self.symbols
+            .get(name)
+            .filter(|symbol| symbol_types.contains(&symbol.symbol_type))
+    }
+
     pub fn add(
         &mut self,
         name: &str,
@@ -43,7 +53,7 @@ impl Scope {
         parameter_types: Vec<PrimitiveType>,
         symbol_type: SymbolType,
     ) -> Symbol {
-        self.last_offset += primitive_type.get_size() as i32 / 8;
+        self.last_offset += primitive_type.get_size() / 8;
 
         let symbol = Symbol {
             symbol_type,
@@ -65,7 +75,7 @@ impl Scope {
         symbol_type: SymbolType,
         offset: i32,
     ) -> Symbol {
-        self.last_offset += primitive_type.get_size() as i32 / 8;
+        self.last_offset += primitive_type.get_size() / 8;
 
         let symbol = Symbol {
             symbol_type,
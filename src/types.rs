@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PrimitiveType {
     Int8,
     Int16,
@@ -10,7 +10,12 @@ pub enum PrimitiveType {
     UInt16,
     UInt32,
     UInt64,
+    Float32,
+    Float64,
     Bool,
+    Char,
+    Pointer(Box<PrimitiveType>),
+    Array(Box<PrimitiveType>, usize),
     Unknown,
     Void,
 }
@@ -26,7 +31,12 @@ impl PrimitiveType {
             PrimitiveType::UInt16 => 16,
             PrimitiveType::UInt32 => 32,
             PrimitiveType::UInt64 => 64,
+            PrimitiveType::Float32 => 32,
+            PrimitiveType::Float64 => 64,
             PrimitiveType::Bool => 8,
+            PrimitiveType::Char => 8,
+            PrimitiveType::Pointer(_) => 64,
+            PrimitiveType::Array(element_type, length) => element_type.get_size() * *length as i32,
             _ => 0,
         }
     }
@@ -51,6 +61,43 @@ impl PrimitiveType {
         )
     }
 
+    pub fn is_integer(&self) -> bool {
+        self.is_signed() || self.is_unsigned()
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, PrimitiveType::Pointer(_))
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, PrimitiveType::Array(_, _))
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, PrimitiveType::Float32 | PrimitiveType::Float64)
+    }
+
+    pub fn switch_sign(&self) -> PrimitiveType {
+        match self {
+            PrimitiveType::Int8 => PrimitiveType::UInt8,
+            PrimitiveType::Int16 => PrimitiveType::UInt16,
+            PrimitiveType::Int32 => PrimitiveType::UInt32,
+            PrimitiveType::Int64 => PrimitiveType::UInt64,
+            PrimitiveType::UInt8 => PrimitiveType::Int8,
+            PrimitiveType::UInt16 => PrimitiveType::Int16,
+            PrimitiveType::UInt32 => PrimitiveType::Int32,
+            PrimitiveType::UInt64 => PrimitiveType::Int64,
+            PrimitiveType::Float32
+            | PrimitiveType::Float64
+            | PrimitiveType::Bool
+            | PrimitiveType::Char
+            | PrimitiveType::Pointer(_)
+            | PrimitiveType::Array(_, _)
+            | PrimitiveType::Unknown
+            | PrimitiveType::Void => PrimitiveType::Unknown,
+        }
+    }
+
     pub fn is_compatible_with(&self, dest_type: &PrimitiveType, one_sided: bool) -> bool {
         if self == dest_type {
             return true;
@@ -60,6 +107,27 @@ impl PrimitiveType {
             return false;
         }
 
+        // A char only converts to and from the byte it is stored in
+        if *self == PrimitiveType::Char || *dest_type == PrimitiveType::Char {
+            return matches!(
+                (self, dest_type),
+                (PrimitiveType::Char, PrimitiveType::UInt8)
+                    | (PrimitiveType::UInt8, PrimitiveType::Char)
+            );
+        }
+
+        // Pointers and arrays only convert to the exact same type
+        if self.is_pointer() || dest_type.is_pointer() || self.is_array() || dest_type.is_array() {
+            return false;
+        }
+
+        // Floats don't mix with integers, they can only be widened to a larger float
+        if self.is_float() || dest_type.is_float() {
+            return self.is_float()
+                && dest_type.is_float()
+                && (!one_sided || dest_type.get_size() > self.get_size());
+        }
+
         if self.is_signed() && dest_type.is_unsigned() {
             return false;
         }
@@ -93,12 +161,16 @@ impl FromStr for PrimitiveType {
             "u16" => Ok(PrimitiveType::UInt16),
             "u32" => Ok(PrimitiveType::UInt32),
             "u64" => Ok(PrimitiveType::UInt64),
+            "f32" => Ok(PrimitiveType::Float32),
+            "f64" => Ok(PrimitiveType::Float64),
             "bool" => Ok(PrimitiveType::Bool),
+            "char" => Ok(PrimitiveType::Char),
             _ => Err(()),
         }
     }
 }
 
+#[allow(dead_code)]
 pub union PrimitiveValue {
     pub uint8: u8,
     pub uint16: u16,
@@ -113,3 +185,125 @@ pub union PrimitiveValue {
     pub float32: f32,
     pub float64: f64,
 }
+
+impl PrimitiveValue {
+    /// Returns the bits of the value, reading the field that belongs to the
+    /// given type. Signed values are sign extended to 64 bits.
+    pub fn as_u64(&self, primitive_type: &PrimitiveType) -> u64 {
+        unsafe {
+            match primitive_type {
+                PrimitiveType::UInt8 | PrimitiveType::Bool | PrimitiveType::Char => {
+                    self.uint8 as u64
+                }
+                PrimitiveType::UInt16 => self.uint16 as u64,
+                PrimitiveType::UInt32 => self.uint32 as u64,
+                PrimitiveType::Int8 => self.int8 as u64,
+                PrimitiveType::Int16 => self.int16 as u64,
+                PrimitiveType::Int32 => self.int32 as u64,
+                PrimitiveType::Int64 => self.int64 as u64,
+                PrimitiveType::Float32 => self.float32.to_bits() as u64,
+                PrimitiveType::Float64 => self.float64.to_bits(),
+                _ => self.uint64,
+            }
+        }
+    }
+
+    /// Returns the value of a float literal of the given type
+    pub fn as_f64(&self, primitive_type: &PrimitiveType) -> f64 {
+        match primitive_type {
+            PrimitiveType::Float32 => f32::from_bits(self.as_u64(primitive_type) as u32) as f64,
+            _ => f64::from_bits(self.as_u64(primitive_type)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_sign_round_trips_integers() {
+        let integers = [
+            PrimitiveType::Int8,
+            PrimitiveType::Int16,
+            PrimitiveType::Int32,
+            PrimitiveType::Int64,
+            PrimitiveType::UInt8,
+            PrimitiveType::UInt16,
+            PrimitiveType::UInt32,
+            PrimitiveType::UInt64,
+        ];
+
+        for integer in integers.iter() {
+            let switched = integer.switch_sign();
+            assert_ne!(switched, *integer);
+            assert_eq!(switched.get_size(), integer.get_size());
+            assert_ne!(switched.is_signed(), integer.is_signed());
+            assert_eq!(switched.switch_sign(), *integer);
+        }
+    }
+
+    #[test]
+    fn switch_sign_of_non_integers_is_unknown() {
+        assert_eq!(PrimitiveType::Bool.switch_sign(), PrimitiveType::Unknown);
+        assert_eq!(PrimitiveType::Float64.switch_sign(), PrimitiveType::Unknown);
+        assert_eq!(
+            PrimitiveType::Pointer(Box::new(PrimitiveType::Int8)).switch_sign(),
+            PrimitiveType::Unknown
+        );
+    }
+
+    #[test]
+    fn as_u64_reads_every_width() {
+        let value = PrimitiveValue { uint8: 0xab };
+        assert_eq!(value.as_u64(&PrimitiveType::UInt8), 0xab);
+        let value = PrimitiveValue { uint16: 0xabcd };
+        assert_eq!(value.as_u64(&PrimitiveType::UInt16), 0xabcd);
+        let value = PrimitiveValue {
+            uint32: 0xabcd_ef01,
+        };
+        assert_eq!(value.as_u64(&PrimitiveType::UInt32), 0xabcd_ef01);
+        let value = PrimitiveValue {
+            uint64: 0xabcd_ef01_2345_6789,
+        };
+        assert_eq!(value.as_u64(&PrimitiveType::UInt64), 0xabcd_ef01_2345_6789);
+        let value = PrimitiveValue {
+            int64: -0x1234_5678_9abc,
+        };
+        assert_eq!(
+            value.as_u64(&PrimitiveType::Int64) as i64,
+            -0x1234_5678_9abc
+        );
+    }
+
+    #[test]
+    fn as_u64_sign_extends_signed_values() {
+        let value = PrimitiveValue { int8: -2 };
+        assert_eq!(value.as_u64(&PrimitiveType::Int8), 0xffff_ffff_ffff_fffe);
+        let value = PrimitiveValue { int16: -300 };
+        assert_eq!(value.as_u64(&PrimitiveType::Int16) as i64, -300);
+        let value = PrimitiveValue { int32: -70000 };
+        assert_eq!(value.as_u64(&PrimitiveType::Int32) as i64, -70000);
+
+        let value = PrimitiveValue { int8: 127 };
+        assert_eq!(value.as_u64(&PrimitiveType::Int8), 127);
+        let value = PrimitiveValue { int16: i16::MIN };
+        assert_eq!(value.as_u64(&PrimitiveType::Int16) as i64, i16::MIN as i64);
+        let value = PrimitiveValue { int32: i32::MIN };
+        assert_eq!(value.as_u64(&PrimitiveType::Int32) as i64, i32::MIN as i64);
+    }
+
+    #[test]
+    fn as_f64_reads_every_width() {
+        let value = PrimitiveValue { float32: 1.5 };
+        assert_eq!(
+            value.as_u64(&PrimitiveType::Float32),
+            1.5f32.to_bits() as u64
+        );
+        assert_eq!(value.as_f64(&PrimitiveType::Float32), 1.5);
+
+        let value = PrimitiveValue { float64: -0.1 };
+        assert_eq!(value.as_u64(&PrimitiveType::Float64), (-0.1f64).to_bits());
+        assert_eq!(value.as_f64(&PrimitiveType::Float64), -0.1);
+    }
+}
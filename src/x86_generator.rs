@@ -2,100 +2,638 @@ use crate::ast::*;
 use crate::generator::*;
 use crate::scope::*;
 use crate::types::*;
+use crate::x86_intel;
+use crate::x86_peephole;
 
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::rc::Rc;
 
+/// The allocatable registers, none of them are used implicitly by an instruction
 const REGISTERS: &[&[&str]] = &[
-    &["%r8b", "%r9b", "%r10b", "%r11b"],
-    &["%r8w", "%r9w", "%r10w", "%r11w"],
-    &["%r8d", "%r9d", "%r10d", "%r11d"],
-    &["%r8", "%r9", "%r10", "%r11"],
+    &["%r8b", "%r9b", "%r10b", "%r11b", "%bl"],
+    &["%r8w", "%r9w", "%r10w", "%r11w", "%bx"],
+    &["%r8d", "%r9d", "%r10d", "%r11d", "%ebx"],
+    &["%r8", "%r9", "%r10", "%r11", "%rbx"],
 ];
 
+/// Whether the callee has to preserve each register of the pool, these are
+/// saved in the prologue of a function which uses them
+const CALLEE_SAVED: &[bool] = &[false, false, false, false, true];
+
 const PARAM_REGISTERS: &[&[&str]] = &[
-    &["%dil", "%sil"],
-    &["%di", "%si"],
-    &["%edi", "%esi"],
-    &["%rdi", "%rsi"],
+    &["%dil", "%sil", "%dl", "%cl", "%r8b", "%r9b"],
+    &["%di", "%si", "%dx", "%cx", "%r8w", "%r9w"],
+    &["%edi", "%esi", "%edx", "%ecx", "%r8d", "%r9d"],
+    &["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"],
 ];
 
+const FLOAT_REGISTERS: &[&str] = &["%xmm8", "%xmm9", "%xmm10", "%xmm11"];
+
+/// The registers which are kept out of the pool, they are clobbered without
+/// the allocator knowing about it. Division and wide multiplication use
+/// %rdx:%rax, %rax also holds return values and global addresses, and shift
+/// counts are passed in %cl.
+const RESERVED_REGISTERS: &[&str] = &["%rax", "%rcx", "%rdx"];
+
 const EAX: &[&str] = &["%al", "%ax", "%eax", "%rax"];
+const REMAINDER: &[&str] = &["%al", "%dx", "%edx", "%rdx"];
+
+const DATA_DIRECTIVES: &[&str] = &[".byte", ".short", ".long", ".quad"];
 
 const MOV_INSTR: &[&str] = &["movb", "movw", "movl", "movq"];
 const ADD_INSTR: &[&str] = &["addb", "addw", "addl", "addq"];
 const SUB_INSTR: &[&str] = &["subb", "subw", "subl", "subq"];
 const MUL_INSTR: &[&str] = &["mulb", "mulw", "mull", "mulq"];
+const WIDE_IMUL_INSTR: &[&str] = &["imulb", "imulw", "imull", "imulq"];
+// The two operand form has no byte variant, so bytes are multiplied as 32 bit values
+const IMUL_INSTR: &[&str] = &["imull", "imulw", "imull", "imulq"];
+const IMUL_OPERAND_INDEX: &[usize] = &[2, 1, 2, 3];
 const DIV_INSTR: &[&str] = &["divb", "divw", "divl", "divq"];
+const IDIV_INSTR: &[&str] = &["idivb", "idivw", "idivl", "idivq"];
+const SIGN_EXTEND_INSTR: &[&str] = &["cbtw", "cwtd", "cltd", "cqto"];
 const CMP_INSTR: &[&str] = &["cmpb", "cmpw", "cmpl", "cmpq"];
 const AND_INSTR: &[&str] = &["andb", "andw", "andl", "andq"];
+const OR_INSTR: &[&str] = &["orb", "orw", "orl", "orq"];
+const XOR_INSTR: &[&str] = &["xorb", "xorw", "xorl", "xorq"];
+const NOT_INSTR: &[&str] = &["notb", "notw", "notl", "notq"];
+const SHL_INSTR: &[&str] = &["shlb", "shlw", "shll", "shlq"];
+const SHR_INSTR: &[&str] = &["shrb", "shrw", "shrl", "shrq"];
+const SAR_INSTR: &[&str] = &["sarb", "sarw", "sarl", "sarq"];
+const NEG_INSTR: &[&str] = &["negb", "negw", "negl", "negq"];
+
+/// The operand size suffixes, indexed like the instruction tables
+const SIZE_SUFFIXES: &[char] = &['b', 'w', 'l', 'q'];
+
+// Float instructions only exist for 32 and 64 bit operands
+const FLOAT_MOV_INSTR: &[&str] = &["movss", "movsd"];
+const FLOAT_ADD_INSTR: &[&str] = &["addss", "addsd"];
+const INT_TO_FLOAT_INSTR: &[&str] = &["cvtsi2ssq", "cvtsi2sdq"];
+const FLOAT_TO_INT_INSTR: &[&str] = &["cvttss2siq", "cvttsd2siq"];
+
+fn float_instruction_index(size_index: usize) -> usize {
+    size_index - 2
+}
+
+/// Returns the mnemonic which sign or zero extends a register to a wider one,
+/// it names both sizes so the assembler doesn't have to infer them
+fn extend_instr(src_index: usize, dest_index: usize, signed: bool) -> String {
+    format!(
+        "mov{}{}{}",
+        if signed { 's' } else { 'z' },
+        SIZE_SUFFIXES[src_index],
+        SIZE_SUFFIXES[dest_index]
+    )
+}
+
+fn comparison_to_set_instr(comparison_type: BinaryOperationType, signed: bool) -> &'static str {
+    match comparison_type {
+        BinaryOperationType::Equals => "sete",
+        BinaryOperationType::NotEquals => "setne",
+        BinaryOperationType::LessThan if signed => "setl",
+        BinaryOperationType::LessThan => "setb",
+        BinaryOperationType::LessThanOrEqual if signed => "setle",
+        BinaryOperationType::LessThanOrEqual => "setbe",
+        BinaryOperationType::GreaterThan if signed => "setg",
+        BinaryOperationType::GreaterThan => "seta",
+        BinaryOperationType::GreaterThanOrEqual if signed => "setge",
+        BinaryOperationType::GreaterThanOrEqual => "setae",
+        _ => panic!(
+            "Trying to get a set instruction for a non comparison operator, {:?}",
+            comparison_type
+        ),
+    }
+}
+
+/// The builtin print functions declared by the parser. Each one moves its
+/// arguments into the value operand of printf and prints it using a format string.
+const RUNTIME_FUNCTIONS: &[(&str, &str, &str)] = &[
+    ("printbool", "movzbl\t%dil, %esi", ".Lformat_d"),
+    ("printchar", "movzbl\t%dil, %esi", ".Lformat_c"),
+    ("print8", "movzbl\t%dil, %esi", ".Lformat_d"),
+    ("print16", "movzwl\t%di, %esi", ".Lformat_d"),
+    ("print32", "movl\t%edi, %esi", ".Lformat_u"),
+    ("print64", "movq\t%rdi, %rsi", ".Lformat_lu"),
+    ("printi8", "movsbl\t%dil, %esi", ".Lformat_d"),
+    ("printi16", "movswl\t%di, %esi", ".Lformat_d"),
+    ("printi32", "movl\t%edi, %esi", ".Lformat_d"),
+    ("printi64", "movq\t%rdi, %rsi", ".Lformat_ld"),
+    ("printsum", "leal\t(%rdi,%rsi), %esi", ".Lformat_d"),
+];
+
+const RUNTIME_FORMATS: &[(&str, &str)] = &[
+    (".Lformat_c", "%c\\n"),
+    (".Lformat_d", "%d\\n"),
+    (".Lformat_u", "%u\\n"),
+    (".Lformat_lu", "%lu\\n"),
+    (".Lformat_ld", "%ld\\n"),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AssemblySyntax {
+    Att,
+    Intel,
+}
 
 pub struct X86CodeGenerator {
-    output: Box<File>,
-    registers: [Option<Register>; 4],
-    label_index: i32,
+    output_path: String,
+    /// The generated assembly, it is only written out when generation is finished
+    output: String,
+    /// The peephole optimizer only runs from level 1 onwards
+    optimization_level: u32,
+    /// The assembly is generated in AT&T syntax and converted when it is finished
+    syntax: AssemblySyntax,
+    /// Whether the generated assembly is also echoed to stdout for debugging
+    echo: bool,
+    registers: RegisterFile,
+    float_registers: RegisterFile,
+    /// The number of labels which were created for every prefix
+    label_counts: HashMap<&'static str, i32>,
+    return_label: String,
+    stack_depth: i32,
+    parameter_area: i32,
+    /// The continue and break labels of the loops surrounding the current node
+    loop_labels: Vec<(String, String)>,
+    /// The string literals which are emitted into .rodata, labeled by their index
+    string_literals: Vec<String>,
+    /// Whether the assembly is linked into a position independent executable
+    pie: bool,
+    /// The functions defined in the program, calls to any other function go
+    /// through the PLT in a position independent executable
+    defined_functions: HashSet<String>,
+    /// The callee saved registers of the pool used by the current function
+    used_callee_saved: Vec<usize>,
+}
+
+impl X86CodeGenerator {
+    pub fn set_syntax(&mut self, syntax: AssemblySyntax) {
+        self.syntax = syntax;
+    }
+
+    pub fn set_pie(&mut self, pie: bool) {
+        self.pie = pie;
+    }
+
+    /// Returns the assembly generated so far
+    pub fn emitted(&self) -> &str {
+        &self.output
+    }
+
+    /// Returns the operand of a call to the given function
+    fn call_target(&self, name: &str) -> String {
+        if self.pie && !self.defined_functions.contains(name) {
+            format!("{}@PLT", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn push(&mut self, register: &str) {
+        self.write(&format!("\tpush\t{}", register));
+        self.stack_depth += 8;
+    }
+
+    fn pop(&mut self, register: &str) {
+        self.write(&format!("\tpop\t\t{}", register));
+        self.stack_depth -= 8;
+    }
+
+    /// Returns the signed displacement from %rbp of a local variable
+    ///
+    /// Loads, stores and array accesses all address a local through this, so
+    /// they can't end up using different slots.
+    fn get_frame_offset(&self, symbol: &Symbol) -> i32 {
+        -(self.parameter_area + symbol.offset)
+    }
+
+    /// Returns the memory operand of a variable or parameter in the current frame.
+    ///
+    /// Register parameters are spilled right below the frame pointer, followed by
+    /// the local variables. Parameters beyond the sixth are passed on the stack
+    /// by the caller and live above the return address.
+    fn get_symbol_address(&self, symbol: &Symbol) -> String {
+        match symbol.symbol_type {
+            SymbolType::Variable => format!("{}(%rbp)", self.get_frame_offset(symbol)),
+            SymbolType::Global => format!("{}(%rip)", symbol.name),
+            SymbolType::FunctionParameter => {
+                let index = symbol.offset as usize;
+
+                if index < PARAM_REGISTERS[0].len() {
+                    format!("-{}(%rbp)", (index + 1) * 8)
+                } else {
+                    format!("{}(%rbp)", 16 + (index - PARAM_REGISTERS[0].len()) * 8)
+                }
+            }
+            SymbolType::Function => {
+                self.error("Trying to get the address of a function symbol");
+                unreachable!();
+            }
+        }
+    }
+
+    /// Returns the memory operand of an array element, widening the index so it
+    /// can be used as the index register of the address.
+    fn get_element_address(
+        &mut self,
+        symbol: &Symbol,
+        index_reg: RegisterGuard,
+    ) -> (String, RegisterGuard) {
+        let element_size = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type.get_size() / 8,
+            _ => {
+                self.error("Trying to index a symbol which is not an array");
+                unreachable!();
+            }
+        };
+
+        let index_reg = if index_reg.size < 64 {
+            let src_index = Self::size_to_instruction_index(index_reg.size);
+            self.gen_widen_instr(index_reg, &PrimitiveType::UInt64, src_index, 3, false)
+        } else {
+            index_reg
+        };
+
+        // A rip relative address can't have an index, so load the base of a global first
+        let address = if symbol.symbol_type == SymbolType::Global {
+            self.write(&format!("\tleaq\t{}(%rip), %rax", symbol.name));
+            format!("(%rax,{},{})", REGISTERS[3][index_reg.index], element_size)
+        } else {
+            format!(
+                "{}(%rbp,{},{})",
+                self.get_frame_offset(symbol),
+                REGISTERS[3][index_reg.index],
+                element_size
+            )
+        };
+
+        (address, index_reg)
+    }
+
+    fn gen_register_move(&mut self, source: &Register, destination: &Register, size_index: usize) {
+        if source.float {
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                FLOAT_MOV_INSTR[float_instruction_index(size_index)],
+                FLOAT_REGISTERS[source.index],
+                FLOAT_REGISTERS[destination.index]
+            ));
+        } else {
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                MOV_INSTR[size_index],
+                REGISTERS[size_index][source.index],
+                REGISTERS[size_index][destination.index]
+            ));
+        }
+    }
+
+    /// Calls a function, the arguments are passed in registers and on the stack
+    fn gen_call(
+        &mut self,
+        name: &str,
+        params: &[AstNode],
+        return_type: &PrimitiveType,
+        variadic: bool,
+    ) -> Option<RegisterGuard> {
+        // The caller saved registers of the pool have to be preserved across the
+        // call when they still hold a live value.
+        let live_regs: Vec<Register> = self
+            .registers
+            .borrow()
+            .iter()
+            .flatten()
+            .filter(|reg| !CALLEE_SAVED[reg.index])
+            .copied()
+            .collect();
+
+        for reg in &live_regs {
+            self.push(REGISTERS[3][reg.index]);
+        }
+
+        // None of the float registers survive a call, push has no form for
+        // them so each live one gets a 16 byte slot to keep the alignment
+        let live_float_regs: Vec<Register> = self
+            .float_registers
+            .borrow()
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+
+        for reg in &live_float_regs {
+            self.write("\tsubq\t$16, %rsp");
+            self.stack_depth += 16;
+            self.write(&format!(
+                "\t{}\t{}, 0(%rsp)",
+                FLOAT_MOV_INSTR[float_instruction_index(Self::size_to_instruction_index(reg.size))],
+                FLOAT_REGISTERS[reg.index]
+            ));
+        }
+
+        let register_param_count = params.len().min(PARAM_REGISTERS[0].len()) as i32;
+        let stack_param_count = params.len() as i32 - register_param_count;
+
+        // The stack has to be 16 byte aligned at the call instruction
+        let padding = (self.stack_depth + stack_param_count * 8) % 16;
+        let argument_area = padding + params.len() as i32 * 8;
+
+        if argument_area != 0 {
+            self.write(&format!("\tsubq\t${}, %rsp", argument_area));
+            self.stack_depth += argument_area;
+        }
+
+        // All arguments are evaluated into the argument area first, the stack
+        // passed ones are already in their final position above the register ones.
+        for (index, param) in params.iter().enumerate() {
+            let primitive_type = param.get_primitive_type();
+            let mut expression_reg = self.gen_expression(param);
+
+            if primitive_type.get_size() < 64 {
+                expression_reg = self.gen_widen_instr(
+                    expression_reg,
+                    &PrimitiveType::UInt64,
+                    Self::size_to_instruction_index(primitive_type.get_size()),
+                    3,
+                    primitive_type.is_signed(),
+                );
+            }
+
+            self.write(&format!(
+                "\t{}\t{}, {}(%rsp)",
+                MOV_INSTR[3],
+                REGISTERS[3][expression_reg.index],
+                index * 8
+            ));
+
+            drop(expression_reg);
+        }
+
+        for param_reg in PARAM_REGISTERS[3]
+            .iter()
+            .take(register_param_count as usize)
+        {
+            self.pop(param_reg);
+        }
+
+        // Variadic functions expect the number of vector registers used in %al
+        if variadic {
+            self.write("\txor\t\t%eax, %eax");
+        }
+
+        let target = self.call_target(name);
+        self.write(&format!("\tcall\t{}", target));
+
+        let remaining_area = padding + stack_param_count * 8;
+        if remaining_area != 0 {
+            self.write(&format!("\taddq\t${}, %rsp", remaining_area));
+            self.stack_depth -= remaining_area;
+        }
+
+        for reg in live_float_regs.iter().rev() {
+            self.write(&format!(
+                "\t{}\t0(%rsp), {}",
+                FLOAT_MOV_INSTR[float_instruction_index(Self::size_to_instruction_index(reg.size))],
+                FLOAT_REGISTERS[reg.index]
+            ));
+            self.write("\taddq\t$16, %rsp");
+            self.stack_depth -= 16;
+        }
+
+        for reg in live_regs.iter().rev() {
+            self.pop(REGISTERS[3][reg.index]);
+        }
+
+        if *return_type == PrimitiveType::Void {
+            return None;
+        }
+
+        let size = return_type.get_size();
+        let register = self.get_register(size);
+        let index = Self::size_to_instruction_index(size);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            MOV_INSTR[index], EAX[index], REGISTERS[index][register.index]
+        ));
+
+        Some(register)
+    }
+
+    fn gen_shift(
+        &mut self,
+        left_reg: Register,
+        right_reg: Register,
+        instr: &str,
+        size_index: usize,
+    ) {
+        // The shift count can only be passed in %cl
+        self.write(&format!(
+            "\t{}\t{}, %cl",
+            MOV_INSTR[0], REGISTERS[0][right_reg.index]
+        ));
+        self.write(&format!(
+            "\t{}\t%cl, {}",
+            instr, REGISTERS[size_index][left_reg.index]
+        ));
+    }
+
+    /// Multiplies into the double width %rdx:%rax pair, for when the high half
+    /// of the product is needed.
+    #[allow(dead_code)]
+    fn gen_wide_multiply(
+        &mut self,
+        left_reg: Register,
+        right_reg: Register,
+        size_index: usize,
+        signed: bool,
+    ) {
+        self.write(&format!(
+            "\t{}\t{}, {}\n\t{}\t{}",
+            MOV_INSTR[size_index],
+            REGISTERS[size_index][left_reg.index],
+            EAX[size_index],
+            if signed {
+                WIDE_IMUL_INSTR[size_index]
+            } else {
+                MUL_INSTR[size_index]
+            },
+            REGISTERS[size_index][right_reg.index]
+        ));
+    }
+
+    /// Divides through %rdx:%rax, the quotient is left in %rax and the
+    /// remainder in %rdx, or in %ah for bytes
+    fn gen_division(
+        &mut self,
+        left_reg: Register,
+        right_reg: Register,
+        size_index: usize,
+        signed: bool,
+    ) {
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            MOV_INSTR[size_index], REGISTERS[size_index][left_reg.index], EAX[size_index]
+        ));
+
+        // The dividend is twice the operand size, so extend it into %ah or %rdx
+        if signed {
+            self.write(&format!("\t{}", SIGN_EXTEND_INSTR[size_index]));
+        } else if size_index == 0 {
+            self.write("\tmovzbw\t%al, %ax");
+        } else {
+            self.write("\txor\t\t%edx, %edx");
+        }
+
+        self.write(&format!(
+            "\t{}\t{}",
+            if signed {
+                IDIV_INSTR[size_index]
+            } else {
+                DIV_INSTR[size_index]
+            },
+            REGISTERS[size_index][right_reg.index]
+        ));
+    }
 }
 
 impl CodeGenerator for X86CodeGenerator {
-    fn new(output_path: &str) -> Self {
+    fn new(output_path: &str, verbose: bool, optimization_level: u32) -> Self {
+        debug_assert!(REGISTERS[3]
+            .iter()
+            .all(|register| !RESERVED_REGISTERS.contains(register)));
+
         X86CodeGenerator {
-            output: Box::new(File::create(output_path).expect("Failed to create output file")),
-            registers: [None; 4],
-            label_index: 0,
+            optimization_level,
+            syntax: AssemblySyntax::Att,
+            output_path: output_path.to_string(),
+            output: String::new(),
+            echo: verbose && output_path != "-",
+            registers: Rc::new(RefCell::new(vec![None; REGISTERS[0].len()])),
+            float_registers: Rc::new(RefCell::new(vec![None; FLOAT_REGISTERS.len()])),
+            label_counts: HashMap::new(),
+            return_label: String::new(),
+            stack_depth: 0,
+            parameter_area: 0,
+            loop_labels: Vec::new(),
+            string_literals: Vec::new(),
+            pie: true,
+            defined_functions: HashSet::new(),
+            used_callee_saved: Vec::new(),
         }
     }
 
     fn write(&mut self, data: &str) {
-        self.output
-            .write_all(data.as_bytes())
+        self.output.push_str(data);
+        self.output.push('\n');
+    }
+
+    fn finish(&mut self) {
+        let mut lines = self.output.lines().map(String::from).collect();
+
+        if self.optimization_level >= 1 {
+            lines = x86_peephole::optimize(lines);
+        }
+        if self.syntax == AssemblySyntax::Intel {
+            lines = x86_intel::to_intel_syntax(lines);
+        }
+
+        self.output = lines.join("\n") + "\n";
+
+        if self.echo {
+            print!("{}", self.emitted());
+        }
+
+        // An output path of - writes the assembly to stdout
+        let mut file: Box<dyn Write> = if self.output_path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(&self.output_path).expect("Failed to create output file"))
+        };
+
+        file.write_all(self.output.as_bytes())
             .expect("Failed to write to output file");
-        self.output
-            .write_all(b"\n")
-            .expect("Failed to write newline to output file");
-        println!("{}", data);
     }
 
-    fn get_label(&mut self) -> i32 {
-        let result = self.label_index;
-        self.label_index += 1;
-        result
+    fn get_named_label(&mut self, prefix: &'static str) -> String {
+        let count = self.label_counts.entry(prefix).or_insert(0);
+        let label = format!(".L{}_{}", prefix, count);
+        *count += 1;
+        label
     }
 
-    fn get_register(&mut self, size: i32) -> Register {
-        for i in 0..self.registers.len() {
-            if self.registers[i].is_none() {
-                let register = Register { size, index: i };
-                self.registers[i] = Some(register);
-                return register;
+    fn get_register(&mut self, size: i32) -> RegisterGuard {
+        let free_index = self.registers.borrow().iter().position(|r| r.is_none());
+        if let Some(index) = free_index {
+            if CALLEE_SAVED[index] && !self.used_callee_saved.contains(&index) {
+                self.used_callee_saved.push(index);
             }
+
+            let register = Register {
+                size,
+                index,
+                float: false,
+            };
+            return RegisterGuard::new(register, Rc::clone(&self.registers));
         }
 
         self.error("Out of registers!");
         unreachable!();
     }
 
-    fn free_register(&mut self, reg: Register) {
-        if self.registers[reg.index].is_none() {
-            self.error("Trying to free a register which is already freed!");
+    fn get_float_register(&mut self, size: i32) -> RegisterGuard {
+        let free_index = self
+            .float_registers
+            .borrow()
+            .iter()
+            .position(|r| r.is_none());
+        if let Some(index) = free_index {
+            let register = Register {
+                size,
+                index,
+                float: true,
+            };
+            return RegisterGuard::new(register, Rc::clone(&self.float_registers));
         }
-        self.registers[reg.index] = None;
+
+        self.error("Out of float registers!");
+        unreachable!();
     }
 
-    fn gen_assignment_instr(&mut self, symbol: &Symbol, register: Register, size_index: usize) {
-        self.write(&format!("\tsubq\t${}, %rsp", symbol.offset));
+    fn gen_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        register: RegisterGuard,
+        size_index: usize,
+    ) {
+        let address = self.get_symbol_address(symbol);
+
+        if register.float {
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                FLOAT_MOV_INSTR[float_instruction_index(size_index)],
+                FLOAT_REGISTERS[register.index],
+                address
+            ));
+
+            return;
+        }
+
         self.write(&format!(
-            "\t{}\t{}, -{}(%rbp)",
-            MOV_INSTR[size_index], REGISTERS[size_index][register.index], symbol.offset
+            "\t{}\t{}, {}",
+            MOV_INSTR[size_index], REGISTERS[size_index][register.index], address
         ));
     }
 
     fn gen_comparison_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-        comparison_type: &str,
-    ) -> Register {
+        comparison_type: BinaryOperationType,
+        signed: bool,
+    ) -> RegisterGuard {
         self.write(&format!(
             "\t{}\t{}, {}",
             CMP_INSTR[size_index],
@@ -104,23 +642,26 @@ impl CodeGenerator for X86CodeGenerator {
         ));
         self.write(&format!(
             "\t{}\t{}",
-            comparison_type, REGISTERS[0][right_reg.index]
+            comparison_to_set_instr(comparison_type, signed),
+            REGISTERS[0][right_reg.index]
         ));
+        // Clear the bits above the flag, a boolean is always exactly 0 or 1 so
+        // ! can flip it with an xor
         self.write(&format!(
-            "\t{}\t$255, {}",
+            "\t{}\t$1, {}",
             AND_INSTR[size_index], REGISTERS[size_index][right_reg.index]
         ));
 
-        self.free_register(left_reg);
+        drop(left_reg);
         right_reg
     }
 
     fn gen_add_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register {
+    ) -> RegisterGuard {
         self.write(&format!(
             "\t{}\t{}, {}",
             ADD_INSTR[size_index],
@@ -128,16 +669,33 @@ impl CodeGenerator for X86CodeGenerator {
             REGISTERS[size_index][left_reg.index]
         ));
 
-        self.free_register(right_reg);
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_float_add_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            FLOAT_ADD_INSTR[float_instruction_index(size_index)],
+            FLOAT_REGISTERS[right_reg.index],
+            FLOAT_REGISTERS[left_reg.index]
+        ));
+
+        drop(right_reg);
         left_reg
     }
 
     fn gen_subtract_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register {
+    ) -> RegisterGuard {
         self.write(&format!(
             "\t{}\t{}, {}",
             SUB_INSTR[size_index],
@@ -145,152 +703,679 @@ impl CodeGenerator for X86CodeGenerator {
             REGISTERS[size_index][left_reg.index]
         ));
 
-        self.free_register(right_reg);
+        drop(right_reg);
         left_reg
     }
 
     fn gen_multiply_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register {
+        _signed: bool,
+    ) -> RegisterGuard {
+        // The low half of the product is the same for signed and unsigned operands
+        let operand_index = IMUL_OPERAND_INDEX[size_index];
         self.write(&format!(
-            "\t{}\t{}, {}\n\t{}\t{}\n\t{}\t{}, {}",
-            MOV_INSTR[size_index],
-            REGISTERS[size_index][right_reg.index],
-            EAX[size_index],
-            MUL_INSTR[size_index],
-            REGISTERS[size_index][left_reg.index],
-            MOV_INSTR[size_index],
-            EAX[size_index],
-            REGISTERS[size_index][left_reg.index]
+            "\t{}\t{}, {}",
+            IMUL_INSTR[size_index],
+            REGISTERS[operand_index][right_reg.index],
+            REGISTERS[operand_index][left_reg.index]
         ));
 
-        self.free_register(right_reg);
+        drop(right_reg);
         left_reg
     }
 
     fn gen_divide_instr(
         &mut self,
-        left_reg: Register,
-        right_reg: Register,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
         size_index: usize,
-    ) -> Register {
+        signed: bool,
+    ) -> RegisterGuard {
+        self.gen_division(*left_reg, *right_reg, size_index, signed);
+
         self.write(&format!(
             "\t{}\t{}, {}",
-            MOV_INSTR[size_index], REGISTERS[size_index][left_reg.index], EAX[size_index]
+            MOV_INSTR[size_index], EAX[size_index], REGISTERS[size_index][left_reg.index]
         ));
-        self.write("\tcltd");
+
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_modulo_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        self.gen_division(*left_reg, *right_reg, size_index, signed);
+
+        // %ah can't be encoded together with the extended registers
+        if size_index == 0 {
+            self.write("\tmovb\t%ah, %al");
+        }
+
         self.write(&format!(
-            "\t{}\t{}",
-            DIV_INSTR[size_index], REGISTERS[size_index][right_reg.index]
+            "\t{}\t{}, {}",
+            MOV_INSTR[size_index], REMAINDER[size_index], REGISTERS[size_index][left_reg.index]
         ));
+
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_bitand_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
         self.write(&format!(
             "\t{}\t{}, {}",
-            MOV_INSTR[size_index], EAX[size_index], REGISTERS[size_index][left_reg.index]
+            AND_INSTR[size_index],
+            REGISTERS[size_index][right_reg.index],
+            REGISTERS[size_index][left_reg.index]
         ));
 
-        self.free_register(right_reg);
+        drop(right_reg);
         left_reg
     }
 
-    fn gen_numeric_literal_instr(
+    fn gen_bitor_instr(
         &mut self,
-        primitive_type: &PrimitiveType,
-        primitive_value: &PrimitiveValue,
-    ) -> Register {
-        let register = self.get_register(primitive_type.get_size());
-
-        //TODO: fix hardcoded union access
-        //TODO: fix hardcoded mov to 64bit reg
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
         self.write(&format!(
-            "\t{}\t${}, {}",
-            MOV_INSTR[3],
-            unsafe { primitive_value.int64 },
-            REGISTERS[3][register.index]
+            "\t{}\t{}, {}",
+            OR_INSTR[size_index],
+            REGISTERS[size_index][right_reg.index],
+            REGISTERS[size_index][left_reg.index]
         ));
 
-        register
+        drop(right_reg);
+        left_reg
     }
 
-    fn gen_widen_instr(
+    fn gen_bitxor_instr(
         &mut self,
-        register: Register,
-        primitive_type: &PrimitiveType,
-        src_index: usize,
-        dest_index: usize,
-    ) -> Register {
-        let result_reg = self.get_register(primitive_type.get_size());
-
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
         self.write(&format!(
-            "\tmovzx\t{}, {}",
-            REGISTERS[src_index][register.index], REGISTERS[dest_index][result_reg.index]
+            "\t{}\t{}, {}",
+            XOR_INSTR[size_index],
+            REGISTERS[size_index][right_reg.index],
+            REGISTERS[size_index][left_reg.index]
         ));
 
-        self.free_register(register);
-
-        result_reg
+        drop(right_reg);
+        left_reg
     }
 
-    fn gen_identifier_instr(&mut self, symbol: &Symbol) -> Register {
-        let size = symbol.primitive_type.get_size();
-        let register = self.get_register(size);
-        let index = Self::size_to_instruction_index(size);
+    fn gen_shift_left_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+    ) -> RegisterGuard {
+        self.gen_shift(*left_reg, *right_reg, SHL_INSTR[size_index], size_index);
 
-        match symbol.symbol_type {
-            SymbolType::Variable => {
-                self.write(&format!(
-                    "\t{}\t-{}(%rbp), {}",
-                    MOV_INSTR[index], symbol.offset, REGISTERS[index][register.index],
-                ));
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_shift_right_instr(
+        &mut self,
+        left_reg: RegisterGuard,
+        right_reg: RegisterGuard,
+        size_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        let instr = if signed {
+            SAR_INSTR[size_index]
+        } else {
+            SHR_INSTR[size_index]
+        };
+
+        self.gen_shift(*left_reg, *right_reg, instr, size_index);
+
+        drop(right_reg);
+        left_reg
+    }
+
+    fn gen_logical_instr(
+        &mut self,
+        left: &AstNode,
+        right: &AstNode,
+        operation_type: BinaryOperationType,
+    ) -> RegisterGuard {
+        let end_label = self.get_named_label("logical_end");
+
+        let left_reg = self.gen_expression(left);
+
+        // Skip the right operand when the left one already determines the result
+        self.write(&format!(
+            "\t{}\t$0, {}",
+            CMP_INSTR[0], REGISTERS[0][left_reg.index]
+        ));
+        match operation_type {
+            BinaryOperationType::LogicalAnd => self.write(&format!("\tje\t\t{}", end_label)),
+            BinaryOperationType::LogicalOr => self.write(&format!("\tjne\t\t{}", end_label)),
+            _ => self.error("Trying to generate a logical instruction for a non logical operator"),
+        }
+
+        let right_reg = self.gen_expression(right);
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            MOV_INSTR[0], REGISTERS[0][right_reg.index], REGISTERS[0][left_reg.index]
+        ));
+        drop(right_reg);
+
+        self.write(&format!("{}:", end_label));
+
+        left_reg
+    }
+
+    fn gen_ternary_instr(
+        &mut self,
+        condition: &AstNode,
+        then_value: &AstNode,
+        else_value: &AstNode,
+    ) -> RegisterGuard {
+        let else_label = self.get_named_label("ternary_else");
+        let end_label = self.get_named_label("ternary_end");
+
+        let condition_reg = self.gen_expression(condition);
+        self.write(&format!(
+            "\t{}\t$0, {}",
+            CMP_INSTR[0], REGISTERS[0][condition_reg.index]
+        ));
+        self.write(&format!("\tjz\t\t{}", else_label));
+        drop(condition_reg);
+
+        // Both branches move their value into the same result register
+        let primitive_type = then_value.get_primitive_type();
+        let size_index = Self::size_to_instruction_index(primitive_type.get_size());
+        let result_reg = if primitive_type.is_float() {
+            self.get_float_register(primitive_type.get_size())
+        } else {
+            self.get_register(primitive_type.get_size())
+        };
+
+        let then_reg = self.gen_expression(then_value);
+        self.gen_register_move(&then_reg, &result_reg, size_index);
+        drop(then_reg);
+        self.write(&format!("\tjmp\t\t{}", end_label));
+
+        self.write(&format!("{}:", else_label));
+        let else_reg = self.gen_expression(else_value);
+        self.gen_register_move(&else_reg, &result_reg, size_index);
+        drop(else_reg);
+
+        self.write(&format!("{}:", end_label));
+
+        result_reg
+    }
+
+    fn gen_negate_instr(&mut self, register: RegisterGuard, size_index: usize) -> RegisterGuard {
+        self.write(&format!(
+            "\t{}\t{}",
+            NEG_INSTR[size_index], REGISTERS[size_index][register.index]
+        ));
+
+        register
+    }
+
+    fn gen_not_instr(&mut self, register: RegisterGuard) -> RegisterGuard {
+        self.write(&format!(
+            "\t{}\t$1, {}",
+            XOR_INSTR[0], REGISTERS[0][register.index]
+        ));
+
+        register
+    }
+
+    fn gen_bitnot_instr(&mut self, register: RegisterGuard, size_index: usize) -> RegisterGuard {
+        self.write(&format!(
+            "\t{}\t{}",
+            NOT_INSTR[size_index], REGISTERS[size_index][register.index]
+        ));
+
+        register
+    }
+
+    fn gen_numeric_literal_instr(
+        &mut self,
+        primitive_type: &PrimitiveType,
+        primitive_value: &PrimitiveValue,
+    ) -> RegisterGuard {
+        // There are no float immediates, so the bits are moved in through %rax
+        if primitive_type.is_float() {
+            let register = self.get_float_register(primitive_type.get_size());
+
+            if *primitive_type == PrimitiveType::Float32 {
+                self.write(&format!(
+                    "\tmovl\t${}, %eax",
+                    primitive_value.as_u64(primitive_type)
+                ));
+                self.write(&format!(
+                    "\tmovd\t%eax, {}",
+                    FLOAT_REGISTERS[register.index]
+                ));
+            } else {
+                let bits = primitive_value.as_u64(primitive_type);
+                let mnemonic = if bits <= i32::MAX as u64 {
+                    "movq"
+                } else {
+                    "movabsq"
+                };
+
+                self.write(&format!("\t{}\t${}, %rax", mnemonic, bits));
+                self.write(&format!(
+                    "\tmovq\t%rax, {}",
+                    FLOAT_REGISTERS[register.index]
+                ));
             }
-            SymbolType::FunctionParameter => {
+
+            return register;
+        }
+
+        let register = self.get_register(primitive_type.get_size());
+
+        if *primitive_type == PrimitiveType::Bool {
+            self.write(&format!(
+                "\t{}\t${}, {}",
+                MOV_INSTR[0],
+                primitive_value.as_u64(primitive_type),
+                REGISTERS[0][register.index]
+            ));
+
+            return register;
+        }
+
+        //TODO: fix hardcoded mov to 64bit reg
+        let value = primitive_value.as_u64(primitive_type);
+        let (immediate, fits_in_32_bits) = if primitive_type.is_signed() {
+            let value = value as i64;
+            (value.to_string(), value == value as i32 as i64)
+        } else {
+            (value.to_string(), value <= i32::MAX as u64)
+        };
+
+        // movq sign extends a 32 bit immediate, larger values need movabsq
+        self.write(&format!(
+            "\t{}\t${}, {}",
+            if fits_in_32_bits {
+                MOV_INSTR[3]
+            } else {
+                "movabsq"
+            },
+            immediate,
+            REGISTERS[3][register.index]
+        ));
+
+        register
+    }
+
+    fn gen_widen_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+        src_index: usize,
+        dest_index: usize,
+        signed: bool,
+    ) -> RegisterGuard {
+        if primitive_type.is_float() {
+            let result_reg = self.get_float_register(primitive_type.get_size());
+
+            self.write(&format!(
+                "\tcvtss2sd\t{}, {}",
+                FLOAT_REGISTERS[register.index], FLOAT_REGISTERS[result_reg.index]
+            ));
+            drop(register);
+
+            return result_reg;
+        }
+
+        let result_reg = self.get_register(primitive_type.get_size());
+
+        if src_index == 2 && !signed {
+            // Writing a 32 bit register implicitly zeroes the upper half
+            self.write(&format!(
+                "\tmovl\t{}, {}",
+                REGISTERS[src_index][register.index], REGISTERS[src_index][result_reg.index]
+            ));
+        } else {
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                extend_instr(src_index, dest_index, signed),
+                REGISTERS[src_index][register.index],
+                REGISTERS[dest_index][result_reg.index]
+            ));
+        }
+
+        drop(register);
+
+        result_reg
+    }
+
+    fn gen_cast_instr(
+        &mut self,
+        register: RegisterGuard,
+        src_type: &PrimitiveType,
+        dest_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        let src_size = src_type.get_size();
+        let dest_size = dest_type.get_size();
+        let src_index = Self::size_to_instruction_index(src_size);
+        let dest_index = Self::size_to_instruction_index(dest_size);
+
+        match (src_type.is_float(), dest_type.is_float()) {
+            (true, true) if src_size == dest_size => register,
+            (true, true) => {
+                let result_reg = self.get_float_register(dest_size);
+
                 self.write(&format!(
                     "\t{}\t{}, {}",
-                    MOV_INSTR[index],
-                    PARAM_REGISTERS[index][symbol.offset as usize],
-                    REGISTERS[index][register.index],
+                    if dest_size > src_size {
+                        "cvtss2sd"
+                    } else {
+                        "cvtsd2ss"
+                    },
+                    FLOAT_REGISTERS[register.index],
+                    FLOAT_REGISTERS[result_reg.index]
                 ));
+                drop(register);
+
+                result_reg
             }
-            _ => {
-                self.error("Trying to generate from function symbol ast node");
+            (false, true) => {
+                // The conversion only takes 32 or 64 bit operands, so always convert from 64 bits
+                let register = if src_size < 64 {
+                    self.gen_widen_instr(
+                        register,
+                        &PrimitiveType::UInt64,
+                        src_index,
+                        3,
+                        src_type.is_signed(),
+                    )
+                } else {
+                    register
+                };
+                let result_reg = self.get_float_register(dest_size);
+
+                self.write(&format!(
+                    "\t{}\t{}, {}",
+                    INT_TO_FLOAT_INSTR[float_instruction_index(dest_index)],
+                    REGISTERS[3][register.index],
+                    FLOAT_REGISTERS[result_reg.index]
+                ));
+                drop(register);
+
+                result_reg
+            }
+            (true, false) => {
+                // Only the low bits of the 64 bit result are used for smaller types
+                let result_reg = self.get_register(dest_size);
+
+                self.write(&format!(
+                    "\t{}\t{}, {}",
+                    FLOAT_TO_INT_INSTR[float_instruction_index(src_index)],
+                    FLOAT_REGISTERS[register.index],
+                    REGISTERS[3][result_reg.index]
+                ));
+                drop(register);
+
+                result_reg
+            }
+            (false, false) if dest_size > src_size => self.gen_widen_instr(
+                register,
+                dest_type,
+                src_index,
+                dest_index,
+                src_type.is_signed(),
+            ),
+            (false, false) if dest_size < src_size => {
+                let result_reg = self.get_register(dest_size);
+
+                self.write(&format!(
+                    "\t{}\t{}, {}",
+                    MOV_INSTR[dest_index],
+                    REGISTERS[dest_index][register.index],
+                    REGISTERS[dest_index][result_reg.index]
+                ));
+                drop(register);
+
+                result_reg
             }
+            (false, false) => register,
+        }
+    }
+
+    fn gen_identifier_instr(&mut self, symbol: &Symbol) -> RegisterGuard {
+        let size = symbol.primitive_type.get_size();
+        let index = Self::size_to_instruction_index(size);
+        let address = self.get_symbol_address(symbol);
+
+        if symbol.primitive_type.is_float() {
+            let register = self.get_float_register(size);
+
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                FLOAT_MOV_INSTR[float_instruction_index(index)],
+                address,
+                FLOAT_REGISTERS[register.index],
+            ));
+
+            return register;
         }
 
+        let register = self.get_register(size);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            MOV_INSTR[index], address, REGISTERS[index][register.index],
+        ));
+
         register
     }
 
-    fn gen_functioncall_instr(&mut self, name: &str, params: &[AstNode]) {
-        assert!(params.len() <= PARAM_REGISTERS.len());
+    fn gen_address_of_instr(&mut self, symbol: &Symbol) -> RegisterGuard {
+        let register = self.get_register(64);
+        let address = self.get_symbol_address(symbol);
+
+        self.write(&format!(
+            "\tleaq\t{}, {}",
+            address, REGISTERS[3][register.index]
+        ));
 
-        let mut allocated_regs: Vec<Register> = Vec::new();
+        register
+    }
 
-        for (index, param) in params.iter().enumerate() {
-            let instr_index =
-                Self::size_to_instruction_index(param.get_primitive_type().get_size());
-            let expression_reg = self.gen_expression(param);
+    fn gen_dereference_instr(
+        &mut self,
+        register: RegisterGuard,
+        primitive_type: &PrimitiveType,
+    ) -> RegisterGuard {
+        let size = primitive_type.get_size();
+        let index = Self::size_to_instruction_index(size);
+
+        if primitive_type.is_float() {
+            let result_reg = self.get_float_register(size);
 
-            //TODO: fix this
             self.write(&format!(
-                "\txor\t\t{},{}",
-                PARAM_REGISTERS[3][index], PARAM_REGISTERS[3][index]
+                "\t{}\t({}), {}",
+                FLOAT_MOV_INSTR[float_instruction_index(index)],
+                REGISTERS[3][register.index],
+                FLOAT_REGISTERS[result_reg.index]
             ));
+            drop(register);
+
+            return result_reg;
+        }
+
+        // The loaded value can replace the pointer in the same register
+        self.write(&format!(
+            "\t{}\t({}), {}",
+            MOV_INSTR[index], REGISTERS[3][register.index], REGISTERS[index][register.index]
+        ));
+
+        register.with_size(size)
+    }
+
+    fn gen_index_instr(&mut self, symbol: &Symbol, index_reg: RegisterGuard) -> RegisterGuard {
+        let (address, index_reg) = self.get_element_address(symbol, index_reg);
+
+        let element_type = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type,
+            _ => unreachable!(),
+        };
+        let size = element_type.get_size();
+        let index = Self::size_to_instruction_index(size);
+
+        if element_type.is_float() {
+            let register = self.get_float_register(size);
+
             self.write(&format!(
                 "\t{}\t{}, {}",
-                MOV_INSTR[instr_index],
-                REGISTERS[instr_index][expression_reg.index],
-                PARAM_REGISTERS[instr_index][index]
+                FLOAT_MOV_INSTR[float_instruction_index(index)],
+                address,
+                FLOAT_REGISTERS[register.index]
             ));
+            drop(index_reg);
 
-            allocated_regs.push(expression_reg);
+            return register;
         }
 
-        for reg in allocated_regs {
-            self.free_register(reg);
+        let register = self.get_register(size);
+
+        self.write(&format!(
+            "\t{}\t{}, {}",
+            MOV_INSTR[index], address, REGISTERS[index][register.index]
+        ));
+        drop(index_reg);
+
+        register
+    }
+
+    fn gen_index_assignment_instr(
+        &mut self,
+        symbol: &Symbol,
+        index_reg: RegisterGuard,
+        register: RegisterGuard,
+    ) {
+        let (address, index_reg) = self.get_element_address(symbol, index_reg);
+        let index = Self::size_to_instruction_index(register.size);
+
+        if register.float {
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                FLOAT_MOV_INSTR[float_instruction_index(index)],
+                FLOAT_REGISTERS[register.index],
+                address
+            ));
+        } else {
+            self.write(&format!(
+                "\t{}\t{}, {}",
+                MOV_INSTR[index], REGISTERS[index][register.index], address
+            ));
+        }
+
+        drop(index_reg);
+        drop(register);
+    }
+
+    fn gen_store_instr(
+        &mut self,
+        pointer_reg: RegisterGuard,
+        register: RegisterGuard,
+        size_index: usize,
+    ) {
+        let source = if register.float {
+            FLOAT_REGISTERS[register.index]
+        } else {
+            REGISTERS[size_index][register.index]
+        };
+        let instr = if register.float {
+            FLOAT_MOV_INSTR[float_instruction_index(size_index)]
+        } else {
+            MOV_INSTR[size_index]
+        };
+
+        self.write(&format!(
+            "\t{}\t{}, ({})",
+            instr, source, REGISTERS[3][pointer_reg.index]
+        ));
+
+        drop(pointer_reg);
+        drop(register);
+    }
+
+    fn gen_functioncall_instr(
+        &mut self,
+        symbol: &Symbol,
+        params: &[AstNode],
+    ) -> Option<RegisterGuard> {
+        self.gen_call(&symbol.name, params, &symbol.primitive_type, false)
+    }
+
+    fn gen_printf_instr(&mut self, params: &[AstNode]) {
+        self.gen_call("printf", params, &PrimitiveType::Void, true);
+    }
+
+    fn gen_assert_instr(&mut self, condition: &AstNode) {
+        let condition_reg = self.gen_expression(condition);
+        let end_label = self.get_named_label("assert_end");
+
+        let instr_index = Self::size_to_instruction_index(condition_reg.size);
+
+        self.write(&format!(
+            "\t{}\t$0, {}",
+            CMP_INSTR[instr_index], REGISTERS[instr_index][condition_reg.index]
+        ));
+        self.write(&format!("\tjnz\t\t{}", end_label));
+        // A failed assert stops the program with an invalid instruction
+        self.write("\tud2");
+        self.write(&format!("{}:", end_label));
+    }
+
+    fn gen_string_literal_instr(&mut self, value: &str) -> RegisterGuard {
+        // Identical strings share the same label
+        let index = match self.string_literals.iter().position(|s| s == value) {
+            Some(index) => index,
+            None => {
+                self.string_literals.push(value.to_string());
+                self.string_literals.len() - 1
+            }
+        };
+
+        let register = self.get_register(64);
+        self.write(&format!(
+            "\tleaq\t.LC{}(%rip), {}",
+            index, REGISTERS[3][register.index]
+        ));
+
+        register
+    }
+
+    fn gen_string_literals(&mut self) {
+        if self.string_literals.is_empty() {
+            return;
         }
 
-        self.write(&format!("\tcall\t{}", name));
+        self.write("\t.section\t.rodata");
+        for (index, value) in std::mem::take(&mut self.string_literals).iter().enumerate() {
+            self.write(&format!(".LC{}:", index));
+            self.write(&format!("\t.asciz\t\"{}\"", escape_string(value)));
+        }
+        self.write("\t.text");
     }
 
     fn gen_if_instr(
@@ -301,10 +1386,10 @@ impl CodeGenerator for X86CodeGenerator {
     ) {
         let has_else = else_code.is_some();
 
-        let condition_reg = self.gen_expression(&condition);
+        let condition_reg = self.gen_expression(condition);
 
-        let else_label = self.get_label();
-        let end_label = self.get_label();
+        let else_label = self.get_named_label("if_else");
+        let end_label = self.get_named_label("if_end");
 
         let instr_index = Self::size_to_instruction_index(condition_reg.size);
 
@@ -313,27 +1398,29 @@ impl CodeGenerator for X86CodeGenerator {
             CMP_INSTR[instr_index], REGISTERS[instr_index][condition_reg.index]
         ));
         self.write(&format!(
-            "\tjz\t\tL{}",
-            if has_else { else_label } else { end_label }
+            "\tjz\t\t{}",
+            if has_else { &else_label } else { &end_label }
         ));
+
+        // The condition is no longer needed once the jump is made
+        drop(condition_reg);
+
         self.gen_node(code);
-        self.write(&format!("\tjmp L{}", end_label));
+        self.write(&format!("\tjmp {}", end_label));
         if has_else {
-            self.write(&format!("L{}:", else_label));
+            self.write(&format!("{}:", else_label));
             if let Some(else_code) = else_code {
                 self.gen_node(else_code);
             }
         }
-        self.write(&format!("L{}:", end_label));
-
-        self.free_register(condition_reg);
+        self.write(&format!("{}:", end_label));
     }
 
     fn gen_while_instr(&mut self, condition: &AstNode, code: &AstNode) {
-        let start_label = self.get_label();
-        let end_label = self.get_label();
+        let start_label = self.get_named_label("while_start");
+        let end_label = self.get_named_label("while_end");
 
-        self.write(&format!("L{}:", start_label));
+        self.write(&format!("{}:", start_label));
 
         let condition_reg = self.gen_expression(condition);
 
@@ -343,32 +1430,291 @@ impl CodeGenerator for X86CodeGenerator {
             "\t{}\t$0, {}",
             CMP_INSTR[instr_index], REGISTERS[instr_index][condition_reg.index]
         ));
-        self.write(&format!("\tjz\t\tL{}", end_label));
+        self.write(&format!("\tjz\t\t{}", end_label));
+        drop(condition_reg);
+
+        self.loop_labels
+            .push((start_label.clone(), end_label.clone()));
         self.gen_node(code);
+        self.loop_labels.pop();
+
+        self.write(&format!("\tjmp\t\t{}", start_label));
+        self.write(&format!("{}:", end_label));
+    }
 
-        self.write(&format!("\tjmp\t\tL{}", start_label));
-        self.write(&format!("L{}:", end_label));
+    fn gen_for_instr(
+        &mut self,
+        init: &AstNode,
+        condition: &AstNode,
+        update: &AstNode,
+        code: &AstNode,
+    ) {
+        self.gen_node(init);
 
-        self.free_register(condition_reg);
+        let start_label = self.get_named_label("for_start");
+        let update_label = self.get_named_label("for_update");
+        let end_label = self.get_named_label("for_end");
+
+        self.write(&format!("{}:", start_label));
+
+        let condition_reg = self.gen_expression(condition);
+
+        let instr_index = Self::size_to_instruction_index(condition_reg.size);
+
+        self.write(&format!(
+            "\t{}\t$0, {}",
+            CMP_INSTR[instr_index], REGISTERS[instr_index][condition_reg.index]
+        ));
+        self.write(&format!("\tjz\t\t{}", end_label));
+        drop(condition_reg);
+
+        // A continue still has to run the update before the next iteration
+        self.loop_labels
+            .push((update_label.clone(), end_label.clone()));
+        self.gen_node(code);
+        self.loop_labels.pop();
+
+        self.write(&format!("{}:", update_label));
+        self.gen_node(update);
+
+        self.write(&format!("\tjmp\t\t{}", start_label));
+        self.write(&format!("{}:", end_label));
     }
 
     fn gen_function_instr(&mut self, symbol: &Symbol, code: &AstNode) {
         assert!(symbol.symbol_type == SymbolType::Function);
 
+        self.return_label = self.get_named_label("return");
+        self.stack_depth = 0;
+        self.used_callee_saved.clear();
+
+        let register_param_count = symbol.parameter_types.len().min(PARAM_REGISTERS[0].len());
+        self.parameter_area = register_param_count as i32 * 8;
+
+        self.write(&format!("\t.type\t{}, @function", symbol.name));
         self.write(&format!("{}:", symbol.name));
+        self.write("\t.cfi_startproc");
         self.write("\tpush\t%rbp");
+        self.write("\t.cfi_def_cfa_offset\t16");
+        self.write("\t.cfi_offset\t%rbp, -16");
         self.write("\tmov\t\t%rsp, %rbp");
+        self.write("\t.cfi_def_cfa_register\t%rbp");
+
+        // The registers the body uses are only known once it is generated, so
+        // the rest of the prologue is inserted here afterwards
+        let prologue_position = self.output.len();
+
+        // Spill the register parameters so they survive calls made by this function
+        for (index, primitive_type) in symbol
+            .parameter_types
+            .iter()
+            .take(register_param_count)
+            .enumerate()
+        {
+            let size_index = Self::size_to_instruction_index(primitive_type.get_size());
+
+            self.write(&format!(
+                "\t{}\t{}, -{}(%rbp)",
+                MOV_INSTR[size_index],
+                PARAM_REGISTERS[size_index][index],
+                (index + 1) * 8
+            ));
+        }
+
         self.gen_node(code);
+
+        // The callee saved registers are pushed below the locals, keep the frame
+        // and the saved registers a multiple of 16 bytes so %rsp stays aligned for calls
+        let saved_registers: Vec<&str> = self
+            .used_callee_saved
+            .iter()
+            .map(|index| REGISTERS[3][*index])
+            .collect();
+        let saved_size = saved_registers.len() as i32 * 8;
+        let frame_size =
+            (self.parameter_area + code.get_stack_size() + saved_size + 15) / 16 * 16 - saved_size;
+
+        let mut prologue = String::new();
+        if frame_size > 0 {
+            prologue.push_str(&format!("\tsubq\t${}, %rsp\n", frame_size));
+        }
+        for (index, register) in saved_registers.iter().enumerate() {
+            prologue.push_str(&format!("\tpush\t{}\n", register));
+            prologue.push_str(&format!(
+                "\t.cfi_offset\t{}, -{}\n",
+                register,
+                16 + frame_size + (index as i32 + 1) * 8
+            ));
+        }
+        self.output.insert_str(prologue_position, &prologue);
+
+        self.write(&format!("{}:", self.return_label));
+
+        // The process exit status is taken from main, so make it well defined
+        if symbol.name == "main" && symbol.primitive_type == PrimitiveType::Void {
+            self.write("\txor\t\t%eax, %eax");
+        }
+
+        // %rsp can still be lowered when returning from inside an expression,
+        // so the saved registers are restored relative to %rbp
+        for (index, register) in saved_registers.iter().enumerate() {
+            self.write(&format!(
+                "\tmovq\t-{}(%rbp), {}",
+                frame_size + (index as i32 + 1) * 8,
+                register
+            ));
+        }
+
         self.write("\tmov\t\t%rbp, %rsp");
         self.write("\tpop\t\t%rbp");
-
-        assert!(symbol.primitive_type == PrimitiveType::Void);
+        self.write("\t.cfi_def_cfa\t%rsp, 8");
         self.write("\tret");
+        self.write("\t.cfi_endproc");
+        self.write(&format!("\t.size\t{}, .-{}", symbol.name, symbol.name));
+    }
+
+    fn gen_global_declaration_instr(
+        &mut self,
+        symbol: &Symbol,
+        initializer: &Option<Box<AstNode>>,
+    ) {
+        let size = symbol.primitive_type.get_size() / 8;
+        let alignment = match &symbol.primitive_type {
+            PrimitiveType::Array(element_type, _) => element_type.get_size() / 8,
+            _ => size,
+        };
+
+        match initializer {
+            Some(initializer) => {
+                let value = match initializer.get_constant_bits(&symbol.primitive_type) {
+                    Some(value) => value,
+                    None => {
+                        self.error("Global variables need a constant initializer");
+                        unreachable!();
+                    }
+                };
+
+                self.write("\t.data");
+                self.write(&format!("\t.align\t{}", alignment));
+                self.write(&format!("{}:", symbol.name));
+                self.write(&format!(
+                    "\t{}\t{}",
+                    DATA_DIRECTIVES[Self::size_to_instruction_index(size * 8)],
+                    value as i64
+                ));
+            }
+            None => {
+                self.write("\t.bss");
+                self.write(&format!("\t.align\t{}", alignment));
+                self.write(&format!("{}:", symbol.name));
+                self.write(&format!("\t.zero\t{}", size));
+            }
+        }
+
+        self.write("\t.text");
+    }
+
+    fn gen_return_instr(&mut self, value: &Option<Box<AstNode>>) {
+        if let Some(value) = value {
+            let register = self.gen_expression(value);
+            let instr_index = Self::size_to_instruction_index(register.size);
+
+            // Narrow values are extended to 32 bits, so callers which read all of
+            // %eax, like the exit status of main, see the right value
+            if instr_index < 2 {
+                let signed = value.get_primitive_type().is_signed();
+
+                self.write(&format!(
+                    "\t{}\t{}, %eax",
+                    extend_instr(instr_index, 2, signed),
+                    REGISTERS[instr_index][register.index]
+                ));
+            } else {
+                self.write(&format!(
+                    "\t{}\t{}, {}",
+                    MOV_INSTR[instr_index],
+                    REGISTERS[instr_index][register.index],
+                    EAX[instr_index]
+                ));
+            }
+
+            drop(register);
+        }
+
+        self.write(&format!("\tjmp\t\t{}", self.return_label));
+    }
+
+    fn gen_break_instr(&mut self, span: Span) {
+        let end_label = match self.loop_labels.last() {
+            Some((_, end_label)) => end_label.clone(),
+            None => {
+                self.error_at(span, "Break statement outside of a loop");
+                unreachable!();
+            }
+        };
+
+        self.write(&format!("\tjmp\t\t{}", end_label));
+    }
+
+    fn gen_continue_instr(&mut self, span: Span) {
+        let continue_label = match self.loop_labels.last() {
+            Some((continue_label, _)) => continue_label.clone(),
+            None => {
+                self.error_at(span, "Continue statement outside of a loop");
+                unreachable!();
+            }
+        };
+
+        self.write(&format!("\tjmp\t\t{}", continue_label));
+    }
+
+    fn gen_runtime(&mut self) {
+        for (name, argument_instr, format) in RUNTIME_FUNCTIONS {
+            self.write(&format!("{}:", name));
+            self.write("\tpush\t%rbp");
+            self.write("\tmov\t\t%rsp, %rbp");
+            self.write(&format!("\t{}", argument_instr));
+            self.write(&format!("\tleaq\t{}(%rip), %rdi", format));
+            self.write("\txor\t\t%eax, %eax");
+            let target = self.call_target("printf");
+            self.write(&format!("\tcall\t{}", target));
+            self.write("\tpop\t\t%rbp");
+            self.write("\tret");
+        }
+
+        self.write("\t.section\t.rodata");
+        for (label, format) in RUNTIME_FORMATS {
+            self.write(&format!("{}:", label));
+            self.write(&format!("\t.string\t\"{}\"", format));
+        }
+    }
+
+    fn gen(&mut self, node: &AstNode) {
+        // Calls can come before the function they call, so the functions are
+        // collected up front
+        if let AstNode::Block(children, _) = node {
+            self.defined_functions = children
+                .iter()
+                .filter_map(|child| match child {
+                    AstNode::Function(symbol, _) => Some(symbol.name.clone()),
+                    _ => None,
+                })
+                .collect();
+        }
+
+        self.write("\t.globl\tmain");
+
+        self.gen_node(node);
+        self.gen_string_literals();
+
+        self.do_post_check();
     }
 
     fn do_post_check(&self) -> bool {
-        for i in 0..self.registers.len() {
-            if self.registers[i].is_some() {
+        let registers = self.registers.borrow();
+        let float_registers = self.float_registers.borrow();
+        for register in registers.iter().chain(float_registers.iter()) {
+            if register.is_some() {
                 self.error("Not all registers were freed!");
                 return false;
             }
@@ -0,0 +1,232 @@
+use crate::x86_peephole::parse_instruction;
+
+use std::collections::HashSet;
+
+/// The instructions which get a size suffix in AT&T syntax
+const SUFFIXED_INSTRUCTIONS: &[&str] = &[
+    "mov", "movabs", "add", "sub", "mul", "imul", "div", "idiv", "cmp", "and", "or", "xor", "not",
+    "shl", "shr", "sar", "neg", "lea", "test", "push", "pop",
+];
+
+/// The operators which Intel syntax would read instead of a symbol name
+const KEYWORDS: &[&str] = &[
+    "byte", "word", "dword", "fword", "qword", "tbyte", "oword", "xmmword", "ymmword", "zmmword",
+    "ptr", "offset", "short", "near", "far", "flat", "and", "or", "xor", "not", "shl", "shr",
+    "mod", "eq", "ne", "lt", "le", "gt", "ge",
+];
+
+const REGISTER_NAMES: &[&str] = &[
+    "al", "bl", "cl", "dl", "ah", "bh", "ch", "dh", "sil", "dil", "bpl", "spl", "ax", "bx", "cx",
+    "dx", "si", "di", "bp", "sp", "eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp", "rax",
+    "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "rip", "eip", "cs", "ds", "es", "fs", "gs",
+    "ss", "st",
+];
+
+/// Checks whether a name can't be used as a symbol in Intel syntax
+fn is_reserved(name: &str) -> bool {
+    let is_numbered = |prefix: &str, suffixes: &[&str]| {
+        name.strip_prefix(prefix).is_some_and(|rest| {
+            let digits = rest.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+            !digits.is_empty()
+                && digits.chars().all(|c| c.is_ascii_digit())
+                && suffixes.contains(&&rest[digits.len()..])
+        })
+    };
+
+    KEYWORDS.contains(&name)
+        || REGISTER_NAMES.contains(&name)
+        || is_numbered("r", &["", "b", "w", "d", "l"])
+        || ["xmm", "ymm", "zmm", "mm", "cr", "dr", "k"]
+            .iter()
+            .any(|prefix| is_numbered(prefix, &[""]))
+}
+
+/// Symbols defined in the program are renamed when Intel syntax reserves
+/// their name, the dot can't be part of a name in the language itself.
+fn rename_symbol(name: &str, renamed: &HashSet<String>) -> String {
+    if renamed.contains(name) {
+        format!("{}.sym", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Renames the symbols in a label or in a directive which refers to a symbol
+fn rename_symbols_in(line: &str, renamed: &HashSet<String>) -> String {
+    let mut result = String::new();
+    let mut word = String::new();
+
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else {
+            result.push_str(&rename_symbol(&word, renamed));
+            word.clear();
+            result.push(c);
+        }
+    }
+
+    result.pop();
+    result
+}
+
+fn size_to_ptr(suffix: char) -> Option<&'static str> {
+    match suffix {
+        'b' => Some("byte ptr"),
+        'w' => Some("word ptr"),
+        'l' => Some("dword ptr"),
+        'q' => Some("qword ptr"),
+        _ => None,
+    }
+}
+
+/// Converts an AT&T mnemonic into its Intel form, together with the size of
+/// its memory operand when the mnemonic is the only place that size is given.
+fn convert_mnemonic(mnemonic: &str, operands: &[&str]) -> (String, Option<&'static str>) {
+    let renamed = match mnemonic {
+        "cbtw" => Some("cbw"),
+        "cwtd" => Some("cwd"),
+        "cltd" => Some("cdq"),
+        "cqto" => Some("cqo"),
+        "cltq" => Some("cdqe"),
+        "cvtsi2ssq" | "cvtsi2ssl" => Some("cvtsi2ss"),
+        "cvtsi2sdq" | "cvtsi2sdl" => Some("cvtsi2sd"),
+        "cvttss2siq" | "cvttss2sil" => Some("cvttss2si"),
+        "cvttsd2siq" | "cvttsd2sil" => Some("cvttsd2si"),
+        _ => None,
+    };
+    if let Some(renamed) = renamed {
+        return (renamed.to_string(), None);
+    }
+
+    // Extending moves name both sizes, like movzbl, the source size is the one
+    // a memory operand needs
+    for (prefix, intel_mnemonic) in [("movz", "movzx"), ("movs", "movsx")] {
+        if let Some(sizes) = mnemonic.strip_prefix(prefix) {
+            let mut sizes = sizes.chars();
+            if let (Some(from), Some(to), None) = (sizes.next(), sizes.next(), sizes.next()) {
+                if let (Some(ptr), Some(_)) = (size_to_ptr(from), size_to_ptr(to)) {
+                    let intel_mnemonic = if from == 'l' {
+                        "movsxd"
+                    } else {
+                        intel_mnemonic
+                    };
+                    return (intel_mnemonic.to_string(), Some(ptr));
+                }
+            }
+        }
+    }
+
+    // movq and movd between general purpose and float registers keep their name
+    let uses_float_register = operands.iter().any(|operand| operand.starts_with("%xmm"));
+
+    if let Some(suffix) = mnemonic.chars().last() {
+        let base = &mnemonic[..mnemonic.len() - 1];
+
+        if let Some(ptr) = size_to_ptr(suffix) {
+            if SUFFIXED_INSTRUCTIONS.contains(&base) && !uses_float_register {
+                // lea only computes the address, so its operand has no size
+                let ptr = if base == "lea" { None } else { Some(ptr) };
+                return (base.to_string(), ptr);
+            }
+        }
+    }
+
+    (mnemonic.to_string(), None)
+}
+
+/// Converts a memory operand like `-8(%rbp,%r8,4)` into `[rbp+r8*4-8]`
+fn convert_memory_operand(operand: &str, renamed: &HashSet<String>) -> String {
+    let open = operand.find('(').unwrap();
+    let displacement = rename_symbol(&operand[..open], renamed);
+    let registers: Vec<&str> = operand[open + 1..operand.len() - 1]
+        .split(',')
+        .map(|register| register.trim().trim_start_matches('%'))
+        .collect();
+
+    let mut address = registers[0].to_string();
+    if let Some(index) = registers.get(1) {
+        address.push('+');
+        address.push_str(index);
+        if let Some(scale) = registers.get(2) {
+            address.push('*');
+            address.push_str(scale);
+        }
+    }
+
+    match displacement.chars().next() {
+        None => {}
+        Some('-') => address.push_str(&displacement),
+        Some(_) => {
+            address.push('+');
+            address.push_str(&displacement);
+        }
+    }
+
+    format!("[{}]", address)
+}
+
+fn convert_operand(operand: &str, ptr: Option<&str>, renamed: &HashSet<String>) -> String {
+    if let Some(immediate) = operand.strip_prefix('$') {
+        immediate.to_string()
+    } else if let Some(register) = operand.strip_prefix('%') {
+        register.to_string()
+    } else if let Some(target) = operand.strip_prefix('*') {
+        convert_operand(target, ptr, renamed)
+    } else if operand.ends_with(')') {
+        match ptr {
+            Some(ptr) => format!("{} {}", ptr, convert_memory_operand(operand, renamed)),
+            None => convert_memory_operand(operand, renamed),
+        }
+    } else {
+        // Labels used as jump and call targets
+        rename_symbol(operand, renamed)
+    }
+}
+
+/// Rewrites the generated AT&T assembly in Intel syntax
+///
+/// The operands are reversed, the sigils are dropped and the operand size,
+/// which AT&T puts in the mnemonic, is moved onto the memory operands.
+pub fn to_intel_syntax(lines: Vec<String>) -> Vec<String> {
+    let renamed: HashSet<String> = lines
+        .iter()
+        .filter_map(|line| line.strip_suffix(':'))
+        .filter(|label| !label.starts_with('\t') && is_reserved(label))
+        .map(String::from)
+        .collect();
+
+    let mut result = Vec::with_capacity(lines.len() + 1);
+    result.push("\t.intel_syntax noprefix".to_string());
+
+    for line in lines {
+        match parse_instruction(&line) {
+            Some((mnemonic, operands)) => {
+                let (mnemonic, ptr) = convert_mnemonic(mnemonic, &operands);
+                let operands: Vec<String> = operands
+                    .iter()
+                    .rev()
+                    .map(|operand| convert_operand(operand, ptr, &renamed))
+                    .collect();
+
+                if operands.is_empty() {
+                    result.push(format!("\t{}", mnemonic));
+                } else {
+                    result.push(format!("\t{}\t{}", mnemonic, operands.join(", ")));
+                }
+            }
+            None if !line.starts_with('\t') => result.push(rename_symbols_in(&line, &renamed)),
+            None => {
+                let directive = line.split_whitespace().next().unwrap_or_default();
+
+                if matches!(directive, ".type" | ".size" | ".globl") {
+                    result.push(rename_symbols_in(&line, &renamed));
+                } else {
+                    result.push(line);
+                }
+            }
+        }
+    }
+
+    result
+}
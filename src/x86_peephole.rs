@@ -0,0 +1,102 @@
+/// Splits an instruction line into its mnemonic and operands
+pub fn parse_instruction(line: &str) -> Option<(&str, Vec<&str>)> {
+    // Labels and directives aren't instructions
+    if !line.starts_with('\t') || line.trim_start().starts_with('.') {
+        return None;
+    }
+
+    let mut parts = line.trim().splitn(2, char::is_whitespace);
+    let mnemonic = parts.next()?;
+    let operands = parts
+        .next()
+        .map(|operands| operands.trim().split(", ").collect())
+        .unwrap_or_default();
+
+    Some((mnemonic, operands))
+}
+
+fn is_mov(mnemonic: &str) -> bool {
+    matches!(mnemonic, "movb" | "movw" | "movl" | "movq")
+}
+
+fn reads_flags(mnemonic: &str) -> bool {
+    (mnemonic.starts_with('j') && mnemonic != "jmp")
+        || mnemonic.starts_with("set")
+        || mnemonic.starts_with("cmov")
+        || mnemonic.starts_with("adc")
+        || mnemonic.starts_with("sbb")
+}
+
+fn writes_flags(mnemonic: &str) -> bool {
+    [
+        "cmp", "test", "add", "sub", "and", "or", "xor", "neg", "imul", "mul", "idiv", "div",
+        "shl", "shr", "sar",
+    ]
+    .iter()
+    .any(|prefix| mnemonic.starts_with(prefix))
+}
+
+/// Checks whether an instruction after the given line might read the flags
+/// before they are overwritten.
+fn flags_are_live_after(lines: &[String], index: usize) -> bool {
+    for line in &lines[index + 1..] {
+        let (mnemonic, _) = match parse_instruction(line) {
+            Some(instruction) => instruction,
+            // Directives don't execute, but control flow can reach a label from elsewhere
+            None if line.starts_with('\t') => continue,
+            None => return true,
+        };
+
+        if reads_flags(mnemonic) {
+            return true;
+        }
+        if writes_flags(mnemonic) || matches!(mnemonic, "jmp" | "call" | "ret") {
+            return false;
+        }
+    }
+
+    false
+}
+
+/// Removes redundant instructions from the generated assembly
+///
+/// A move which copies a value back to where it just came from is dropped and
+/// zeroing a register with a move is replaced by the shorter xor when the flags
+/// it clobbers are not used.
+pub fn optimize(lines: Vec<String>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::with_capacity(lines.len());
+
+    for (index, line) in lines.iter().enumerate() {
+        if let Some((mnemonic, operands)) = parse_instruction(line) {
+            if is_mov(mnemonic) && operands.len() == 2 {
+                let previous = result.last().and_then(|line| parse_instruction(line));
+
+                if let Some((previous_mnemonic, previous_operands)) = previous {
+                    if previous_mnemonic == mnemonic
+                        && previous_operands.len() == 2
+                        && previous_operands[0] == operands[1]
+                        && previous_operands[1] == operands[0]
+                    {
+                        continue;
+                    }
+                }
+
+                if operands[0] == "$0"
+                    && operands[1].starts_with('%')
+                    && !flags_are_live_after(&lines, index)
+                {
+                    let xor_mnemonic = mnemonic.replacen("mov", "xor", 1);
+                    result.push(format!(
+                        "\t{}\t{}, {}",
+                        xor_mnemonic, operands[1], operands[1]
+                    ));
+                    continue;
+                }
+            }
+        }
+
+        result.push(line.clone());
+    }
+
+    result
+}
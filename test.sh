@@ -6,11 +6,16 @@ if [ $COMPILE_RESULT -ne 0 ]; then
     exit 1
 fi
 
-echo "\nRunning tests..."
+# The Intel syntax is converted from the AT&T output, so every example runs in both
+for syntax in att intel
+do
+for level in 0 1
+do
+echo "\nRunning tests at -O$level with $syntax syntax..."
 for file in examples/*.sq
 do
     echo -n "Running $file..."
-    cargo run $file > /dev/null 2>&1
+    cargo run $file -O$level --syntax $syntax > /dev/null 2>&1
     CARGO_RESULT=$?
     if [ $CARGO_RESULT -ne 0 ]; then
         echo
@@ -44,13 +49,368 @@ do
         exit 1
     fi
 done
+done
+done
+
+echo "\nRunning optimization level test..."
+if echo 'print32(2 + 2);' | cargo run - -O0 -o - 2>/dev/null | grep -q '\$4,'; then
+    echo "Constants were folded at -O0!"
+    exit 1
+fi
+if ! echo 'print32(2 + 2);' | cargo run - -O1 -o - 2>/dev/null | grep -q '\$4,'; then
+    echo "Constants weren't folded at -O1!"
+    exit 1
+fi
+
+echo -n "Running peephole optimization..."
+SOURCE='fn main() { var a: u32 = 1; var b: u32 = 2; a = b = 5; print32(a); print32(b); }'
+echo "$SOURCE" | cargo run - -O0 -o - 2>/dev/null | grep -A1 'movl	%r8d, -8(%rbp)' | grep -q 'movl	-8(%rbp), %r8d'
+RELOAD_RESULT=$?
+echo "$SOURCE" | cargo run - -O1 -o - 2>/dev/null | grep -A1 'movl	%r8d, -8(%rbp)' | grep -q 'movl	-8(%rbp), %r8d'
+PAIR_RESULT=$?
+SOURCE='fn main() { var x: u32 = 0; print32(x); }'
+ASM=$(echo "$SOURCE" | cargo run - -O1 -o - 2>/dev/null)
+echo "$ASM" | grep -q 'xorq	%r8, %r8' && ! echo "$ASM" | grep -q 'movq	\$0, %r8'
+XOR_RESULT=$?
+# The loop condition after the label can be reached with live flags, so the move stays
+SOURCE='fn main() { var i: u32 = 0; while i < 3 { i = i + 1; } print32(i); }'
+echo "$SOURCE" | cargo run - -O1 -o - 2>/dev/null | grep -A2 'movq	\$0, %r8' | grep -q '.Lwhile_start'
+FLAGS_RESULT=$?
+if [ $RELOAD_RESULT -ne 0 ] || [ $PAIR_RESULT -eq 0 ] || [ $XOR_RESULT -ne 0 ] || [ $FLAGS_RESULT -ne 0 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo "\nRunning stdin test..."
+echo -n "Running source from stdin..."
+echo 'print32(5);' | cargo run - > /dev/null 2>&1
+if [ $? -ne 0 ] || ! gcc lib.c output.s || [ "$(./a.out)" != "5" ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running exit code of a parser error..."
+echo 'print32(x);' | cargo run - > /dev/null 2>&1
+if [ $? -ne 3 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running exit code of a generator error..."
+ERRORS=$(echo 'fn main() { var x: u64 = 1; print64(x + (x + (x + (x + (x + (x + x)))))); }' | cargo run -q - -o - 2>&1 >/dev/null)
+if [ $? -ne 4 ] || ! echo "$ERRORS" | grep -q 'Out of registers' || echo "$ERRORS" | grep -q 'panicked'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running unused variable warning..."
+if ! echo 'fn main() { var x: u8; }' | cargo run - 2>&1 | grep -q "unused variable x"; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running unused variable assigned in a block expression..."
+if ! echo 'fn main() { var z: u32 = 0; var y: u32 = { z = 5; 1 }; print32(y); }' | cargo run - 2>&1 | grep -q "unused variable z"; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running exit code of main..."
+echo 'fn main(): i32 { return 42; }' | cargo run - -o - 2>/dev/null | grep -q '\$42,'
+ASM_RESULT=$?
+echo 'fn main(): i32 { return 42; }' | cargo run - > /dev/null 2>&1 && gcc lib.c output.s 2>/dev/null && ./a.out
+EXIT_CODE=$?
+if [ $ASM_RESULT -ne 0 ] || [ $EXIT_CODE -ne 42 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running Intel syntax..."
+SOURCE='fn main() { var x: u32 = 5; var y: u32 = x; print32(y); }'
+echo "$SOURCE" | cargo run - --syntax intel -o - 2>/dev/null | grep -q 'mov	dword ptr \[rbp-8\], r8d'
+ASM_RESULT=$?
+echo "$SOURCE" | cargo run - --syntax intel > /dev/null 2>&1 && gcc lib.c output.s 2>/dev/null
+BUILD_RESULT=$?
+if [ $ASM_RESULT -ne 0 ] || [ $BUILD_RESULT -ne 0 ] || [ "$(./a.out)" != "5" ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running AArch64 target..."
+if ! echo 'fn main() { var a: u32 = 2; var b: u32 = 3; print32(a + b); }' | cargo run - --target aarch64 -o - 2>/dev/null | grep -q 'add	w9, w9, w10'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running IR output..."
+IR=$(echo 'fn main() { var a: u64 = 2 + 3; if a == 5 { print64(a); } }' | cargo run - --emit ir 2>/dev/null)
+EXPECTED_IR=$(printf 'function main:\n\tt0 = 2\n\tt1 = 3\n\tt2 = t0 + t1\n\ta = t2\n\tt3 = a\n\tt4 = 5\n\tt5 = (UInt64) t4\n\tt6 = t3 == t5\n\tif_false t6 goto .Lif_end_0\n\tt7 = a\n\tparam t7\n\tcall print64, 1\n.Lif_end_0:\nend')
+if [ "$IR" != "$EXPECTED_IR" ]; then
+    echo " ${bold}⨯${normal}"
+    echo -e "$IR"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running IR output with many live values..."
+IR=$(echo 'fn main() { var a: u64 = 1; print64(a + (a + (a + (a + a)))); }' | cargo run - --emit ir 2>/dev/null)
+EXPECTED_IR=$(printf 'function main:\n\tt0 = 1\n\ta = t0\n\tt1 = a\n\tt2 = a\n\tt3 = a\n\tt4 = a\n\tt5 = a\n\tt6 = t4 + t5\n\tt7 = t3 + t6\n\tt8 = t2 + t7\n\tt9 = t1 + t8\n\tparam t9\n\tcall print64, 1\nend')
+if [ "$IR" != "$EXPECTED_IR" ]; then
+    echo " ${bold}⨯${normal}"
+    echo -e "$IR"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running identifiers with underscores..."
+TOKENS=$(echo 'snake_case_name _hidden' | cargo run - --emit tokens 2>/dev/null)
+if ! echo "$TOKENS" | grep -q 'Identifier, value: "snake_case_name"' || ! echo "$TOKENS" | grep -q 'Identifier, value: "_hidden"'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running constant overflow..."
+SOURCE='fn main() { var x: u8 = 200 + 100; print8(x); }'
+echo "$SOURCE" | cargo run - -O 1 -o - 2>&1 >/dev/null | grep -q "doesn't fit in UInt8, it wraps around to 44"
+WARNING_RESULT=$?
+echo "$SOURCE" | cargo run - -O 1 --overflow error -o - > /dev/null 2>&1
+EXIT_CODE=$?
+if [ $WARNING_RESULT -ne 0 ] || [ $EXIT_CODE -ne 5 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running assert..."
+ASM=$(echo 'fn main() { assert(1 == 1); }' | cargo run - -o - 2>/dev/null)
+echo "$ASM" | grep -A1 'jnz		.Lassert_end_0' | grep -q 'ud2'
+ASM_RESULT=$?
+echo 'fn main() { assert(1 == 2); }' | cargo run - > /dev/null 2>&1 && gcc lib.c output.s 2>/dev/null && { ./a.out; } 2>/dev/null
+EXIT_CODE=$?
+if [ $ASM_RESULT -ne 0 ] || [ $EXIT_CODE -ne 132 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running unreachable code..."
+WARNINGS=$(printf 'fn f(x: u32): u32 {\n    if x > 1 { return 1; }\n    return 2;\n    print32(x);\n}\nfn main() { print32(f(1)); }\n' | cargo run - -o - 2>&1 >/dev/null)
+if ! echo "$WARNINGS" | grep -A1 'line 4:5' | grep -q 'unreachable statement' || [ $(echo "$WARNINGS" | grep -c 'unreachable') -ne 1 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running full range u64 literals..."
+if ! echo 'fn main() { var x: u64 = 18446744073709551615; print64(x); }' | cargo run - -o - 2>/dev/null | grep -q 'movabsq	\$18446744073709551615, %r8'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running 64 bit immediates..."
+ASM=$(echo 'fn main() { var a: u64 = 4_294_967_296; var b: u64 = 2147483647; var c: f64 = 1.5; print64(a + b); }' | cargo run - -o - 2>/dev/null)
+if ! echo "$ASM" | grep -q 'movabsq	\$4294967296, %r8' || ! echo "$ASM" | grep -q 'movq	\$2147483647, %r8' || ! echo "$ASM" | grep -q 'movabsq	\$4609434218613702656, %rax'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running hex escapes above the ASCII range..."
+if ! printf '%s\n' 'fn main() { printf("\xff\x80é\n"); }' | cargo run - -o - 2>/dev/null | grep -q '.asciz	"\\377\\200\\303\\251\\n"'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running symbol table dump..."
+if ! echo 'fn main() {}' | cargo run - --dump-symbols -o - 2>/dev/null | grep -q 'print32 *Function *Void *\[UInt32\]'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running empty program..."
+printf '' | cargo run - -o - 2>&1 >/dev/null | grep -q 'empty program'
+WARNING_RESULT=$?
+printf '' | cargo run - > /dev/null 2>&1 && gcc lib.c output.s 2>/dev/null && ./a.out
+EXIT_CODE=$?
+if [ $WARNING_RESULT -ne 0 ] || [ $EXIT_CODE -ne 0 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running chained assignment..."
+ASM=$(echo 'fn main() { var a: u32 = 1; var b: u32 = 2; a = b = 5; print32(a); print32(b); }' | cargo run - -o - 2>/dev/null)
+if ! echo "$ASM" | grep -A3 'movq	\$5, %r8' | tr '\n' ' ' | grep -q 'movl	%r8d, -8(%rbp).*movl	-8(%rbp), %r8d.*movl	%r8d, -4(%rbp)'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running redeclaration error location..."
+ERRORS=$(cargo run examples/failing/f020-redeclaration.sq -o - 2>&1 >/dev/null)
+PARAMETER_ERRORS=$(printf 'fn f(a: u8,\n  a: u8) {}\n' | cargo run - -o - 2>&1 >/dev/null)
+FUNCTION_ERRORS=$(printf 'fn f() {}\n\nfn f() {}\n' | cargo run - -o - 2>&1 >/dev/null)
+if ! echo "$ERRORS" | grep -A1 'line 3:9' | grep -q 'Redeclaration of x' || ! echo "$PARAMETER_ERRORS" | grep -A1 'line 2:3' | grep -q 'Redeclaration of a' || ! echo "$FUNCTION_ERRORS" | grep -A1 'line 3:4' | grep -q 'Redeclaration of f'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running return type error location..."
+VOID_ERRORS=$(cargo run examples/failing/f012-return-void.sq -o - 2>&1 >/dev/null)
+TYPE_ERRORS=$(cargo run examples/failing/f013-return-type.sq -o - 2>&1 >/dev/null)
+if ! echo "$VOID_ERRORS" | grep -A1 'line 2:12' | grep -q 'Incompatible return type' || ! echo "$TYPE_ERRORS" | grep -A1 'line 2:12' | grep -q 'Incompatible return type'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running untyped variable error location..."
+if ! cargo run examples/failing/f019-untyped-variable.sq -o - 2>&1 >/dev/null | grep -A1 'line 2:9' | grep -q 'needs either a type or an initializer'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running missing parameter error..."
+if ! cargo run examples/failing/f063-double-trailing-comma.sq -o - 2>&1 >/dev/null | grep -A1 'line 1:12' | grep -q 'Expected a parameter'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running assignment type error location..."
+ERRORS=$(cargo run examples/failing/f065-chained-assignment-types.sq -o - 2>&1 >/dev/null)
+if [ $? -ne 3 ] || ! echo "$ERRORS" | grep -A1 'line 4:5' | grep -q 'Incompatible types in assignment, UInt32 = Bool'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running position independent code..."
+SOURCE='fn main() { print32(5); }'
+echo "$SOURCE" | cargo run - -o - 2>/dev/null | grep -q 'call	print32@PLT$'
+PIE_RESULT=$?
+echo "$SOURCE" | cargo run - --no-pie -o - 2>/dev/null | grep -q 'call	print32$'
+NO_PIE_RESULT=$?
+if [ $PIE_RESULT -ne 0 ] || [ $NO_PIE_RESULT -ne 0 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running non boolean condition..."
+ERRORS=$(printf 'fn main() {\n    var count: u32 = 3;\n    if count {}\n}\n' | cargo run - -o - 2>&1 >/dev/null)
+if ! echo "$ERRORS" | grep -A1 'line 3:8' | grep -q 'found UInt32'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running sizeof..."
+AST=$(echo 'fn main() { print64(sizeof(u16)); print64(sizeof(u32) * 4); }' | cargo run - -O 1 --emit ast 2>/dev/null)
+if ! echo "$AST" | grep -q 'UInt64: 2$' || ! echo "$AST" | grep -q 'UInt64: 16$'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+if ! cargo run examples/failing/f068-sizeof-not-a-type.sq -o - 2>&1 >/dev/null | grep -q 'sizeof expects a type'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running multiplication without %rdx..."
+SOURCE='fn main() { var a: u64 = 3; var b: u64 = 4; var c: u8 = 5; var d: u8 = 6; var e: i32 = 7; var f: i32 = 8; print64(a * b); print8(c * d); printi32(e * f); }'
+MAIN=$(echo "$SOURCE" | cargo run - -o - 2>/dev/null | sed -n '/^main:/,/endproc/p')
+if [ $(echo "$MAIN" | grep -c 'imul') -ne 3 ] || echo "$MAIN" | grep -q -E '%(rdx|edx|dx|dl)\b'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running callee saved registers..."
+SOURCE='fn nested(a: u64, b: u64, c: u64, d: u64, e: u64): u64 { return a + (b + (c + (d + e))); } fn main() { print64(nested(1, 2, 3, 4, 5)); }'
+ASM=$(echo "$SOURCE" | cargo run - -o - 2>/dev/null)
+NESTED=$(echo "$ASM" | sed -n '/^nested:/,/endproc/p' | grep 'rbx' | tr '\n' ' ')
+if ! echo "$NESTED" | grep -q 'push	%rbx.*movq	-40(%rbp), %rbx.*movq	-48(%rbp), %rbx' || [ $(echo "$ASM" | grep -c 'push	%rbx') -ne 1 ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running callee saved registers on an early return..."
+# g is local to the generated assembly, so the caller which sets %rbx is appended to it
+SOURCE='extern fn check_rbx(c: bool): u64; fn id(a: u64, b: u64): u64 { return a; } fn g(c: bool): u64 { var x: u64 = 1; var y: u64 = x + (x + (x + (x + x))); return id(y, { if c { return 7; } 2 }); } fn main() { print64(check_rbx(true)); print64(check_rbx(false)); }'
+echo "$SOURCE" | cargo run - > /dev/null 2>&1
+printf '\t.globl\tcheck_rbx\ncheck_rbx:\n\tpush\t%%rbx\n\tmovq\t$4660, %%rbx\n\tcall\tg\n\tmovq\t%%rbx, %%rax\n\tpop\t\t%%rbx\n\tret\n' >> output.s
+if ! gcc lib.c output.s 2>/dev/null || [ "$(./a.out)" != "$(printf '4660\n4660')" ]; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running comparison results..."
+ASM=$(echo 'fn main() { var a: u64 = 3; printbool(a < 5); }' | cargo run - -o - 2>/dev/null)
+if ! echo "$ASM" | grep -A1 'setb	%r10b' | grep -q 'andq	\$1, %r10$'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running mixed top level..."
+ASM=$(echo 'helper(); fn helper() { print32(5); } helper();' | cargo run - -o - 2>/dev/null)
+if [ $(echo "$ASM" | sed -n '/^main:/,/endproc/p' | grep -c 'call	helper$') -ne 2 ] || echo "$ASM" | sed -n '/^helper:/,/endproc/p' | grep -q 'call	helper'; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
+
+echo -n "Running widening mnemonics..."
+SOURCE='var a: u8 = 1; var b: u16 = 2; var c: i8 = 3; var d: i16 = 4; print64(a as u64); print32(b as u32); printi64(c as i64); printi32(d as i32);'
+ASM=$(echo "$SOURCE" | cargo run - -o - 2>/dev/null)
+for instr in 'movzbq	%r8b, %r9' 'movzwl	%r8w, %r9d' 'movsbq	%r8b, %r9' 'movswl	%r8w, %r9d'; do
+    if ! echo "$ASM" | grep -q "$instr"; then
+        echo " ${bold}⨯${normal}"
+        exit 1
+    fi
+done
+echo " ${bold}✓${normal}"
 
-echo "\nRunnig failing tests..."
+echo -n "Running register guards..."
+# The post check of every generator fails when a register is still allocated
+for options in "" "--syntax intel" "--emit ir"; do
+    if ! cargo run examples/084-assignment-registers.sq $options -o /dev/null > /dev/null 2>&1; then
+        echo " ${bold}⨯${normal}"
+        exit 1
+    fi
+done
+if ! echo 'fn main() { var a: u32 = 2; var b: u32 = a * 3 - 1; b = b / a + a; print32(b); }' | cargo run - --target aarch64 -o /dev/null > /dev/null 2>&1; then
+    echo " ${bold}⨯${normal}"
+    exit 1
+fi
+echo " ${bold}✓${normal}"
 
+# Errors can't depend on the optimization level
+for level in 0 1
+do
+echo "\nRunnig failing tests at -O$level..."
 for file in examples/failing/*.sq
 do
     echo -n "Runnig $file..."
-    cargo run $file > /dev/null 2>&1
+    cargo run $file -O$level > /dev/null 2>&1
     CARGO_RESULT=$?
     if [ $CARGO_RESULT -ne 0 ]; then
         echo " ${bold}✓${normal}"
@@ -59,5 +419,6 @@ do
         exit 1 
     fi
 done
+done
 
 rm output.s a.out